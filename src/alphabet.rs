@@ -0,0 +1,82 @@
+
+use crate::cmdline::AlphabetMode;
+
+/// What an `AlphabetNormalizer` changed in one genome.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AlphabetCounts {
+    pub u_to_t: u64,
+    pub ambiguous: u64,
+    pub stripped: u64,
+    pub replaced: u64,
+}
+
+/// Sequence alphabet checks: U becomes T, the other IUPAC ambiguity codes
+/// become N, spaces, tabs and digits are removed and anything else is
+/// replaced with N or rejected. Case is kept, so soft-masking survives.
+#[derive(Debug, Default)]
+pub struct AlphabetNormalizer {
+    mode: AlphabetMode,
+    counts: AlphabetCounts,
+}
+
+impl AlphabetNormalizer {
+    pub fn new(mode: AlphabetMode) -> Self {
+        Self { mode, counts: AlphabetCounts::default() }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.mode != AlphabetMode::Keep
+    }
+
+    /// Append the normalised `seq` to `out`. Line endings are passed
+    /// through. With `reject`, returns the first character that is neither
+    /// a nucleotide nor an IUPAC code.
+    pub fn normalize(&mut self, seq: &[u8], out: &mut Vec<u8>) -> Result<(), u8> {
+        for &base in seq {
+            let n = if base.is_ascii_lowercase() { b'n' } else { b'N' };
+            match base.to_ascii_uppercase() {
+                b'A' | b'C' | b'G' | b'T' | b'N' | b'\n' | b'\r' => out.push(base),
+                b'U' => {
+                    self.counts.u_to_t += 1;
+                    out.push(base - b'U' + b'T');
+                }
+                b'R' | b'Y' | b'S' | b'W' | b'K' | b'M' | b'B' | b'D' | b'H' | b'V' => {
+                    self.counts.ambiguous += 1;
+                    out.push(n);
+                }
+                b' ' | b'\t' | b'0'..=b'9' => self.counts.stripped += 1,
+                _ if self.mode == AlphabetMode::Reject => return Err(base),
+                _ => {
+                    self.counts.replaced += 1;
+                    out.push(n);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Like `normalize`, for sequence that still has its line breaks: lines
+    /// that only held spaces and digits are dropped rather than left empty.
+    pub fn normalize_lines(&mut self, seq: &[u8], out: &mut Vec<u8>) -> Result<(), u8> {
+        let is_line_end = |b: &u8| matches!(b, b'\n' | b'\r');
+        let begin = out.len();
+        for line in seq.split_inclusive(|&b| b == b'\n') {
+            let start = out.len();
+            self.normalize(line, out)?;
+            if out[start..].iter().all(is_line_end) && !line.iter().all(is_line_end) {
+                out.truncate(start);
+            }
+        }
+        // A dropped last line leaves the line break before it behind.
+        if !seq.ends_with(b"\n") {
+            while out.len() > begin && out.last().is_some_and(is_line_end) {
+                out.pop();
+            }
+        }
+        Ok(())
+    }
+
+    pub fn counts(&self) -> AlphabetCounts {
+        self.counts
+    }
+}
@@ -0,0 +1,171 @@
+
+use std::io::{Error, Write};
+use libdeflater::{Compressor, CompressionLvl, crc32};
+use rayon::prelude::*;
+
+// Same limits as htslib: at most 0xff00 bytes of input per block, so the
+// compressed block always fits into the 16-bit BSIZE field.
+pub const BGZF_BLOCK_SIZE: usize = 0xff00;
+pub const BGZF_MAX_BLOCK_SIZE: usize = 0x10000;
+
+const BLOCK_HEADER_LENGTH: usize = 18;
+const BLOCK_FOOTER_LENGTH: usize = 8;
+
+const BGZF_HEADER: [u8; 16] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+];
+
+pub const BGZF_EOF: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+    0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00,
+];
+
+fn compression_lvl(level: Option<u32>) -> CompressionLvl {
+    level
+        .and_then(|l| CompressionLvl::new(l as i32).ok())
+        .unwrap_or_default()
+}
+
+/// Compress `data` (at most `BGZF_BLOCK_SIZE` bytes) into one BGZF block.
+fn compress_block(compressor: &mut Compressor, data: &[u8], block: &mut Vec<u8>) -> std::io::Result<()> {
+    block.clear();
+    block.resize(BGZF_MAX_BLOCK_SIZE, 0);
+    block[..BGZF_HEADER.len()].copy_from_slice(&BGZF_HEADER);
+
+    let limit = BGZF_MAX_BLOCK_SIZE - BLOCK_HEADER_LENGTH - BLOCK_FOOTER_LENGTH;
+    let compressed_size = match compressor.deflate_compress(data, &mut block[BLOCK_HEADER_LENGTH..BLOCK_HEADER_LENGTH + limit]) {
+        Ok(size) => size,
+        // Incompressible input: fall back to a stored deflate block.
+        Err(_) => Compressor::new(CompressionLvl::new(0).unwrap())
+            .deflate_compress(data, &mut block[BLOCK_HEADER_LENGTH..BLOCK_HEADER_LENGTH + limit])
+            .map_err(|e| Error::other(format!("BGZF block compression failed: {:?}", e)))?,
+    };
+
+    let block_size = BLOCK_HEADER_LENGTH + compressed_size + BLOCK_FOOTER_LENGTH;
+    block[16..18].copy_from_slice(&((block_size - 1) as u16).to_le_bytes());
+    let footer = BLOCK_HEADER_LENGTH + compressed_size;
+    block[footer..footer + 4].copy_from_slice(&crc32(data).to_le_bytes());
+    block[footer + 4..footer + 8].copy_from_slice(&(data.len() as u32).to_le_bytes());
+    block.truncate(block_size);
+    Ok(())
+}
+
+/// Pure-Rust BGZF writer, compatible with `bgzip` and htslib readers.
+/// With more than one thread, `threads` blocks are buffered and compressed
+/// in parallel on the rayon pool; the output does not depend on `threads`.
+pub struct BgzfWriter<W: Write> {
+    inner: W,
+    level: CompressionLvl,
+    threads: usize,
+    compressor: Compressor,
+    buffer: Vec<u8>,
+    block: Vec<u8>,
+    // (compressed, uncompressed) start offset of every block written so far.
+    blocks: Vec<(u64, u64)>,
+    compressed_offset: u64,
+    uncompressed_offset: u64,
+    finished: bool,
+}
+
+impl<W: Write> BgzfWriter<W> {
+    pub fn new(inner: W, level: Option<u32>, threads: usize) -> Self {
+        let level = compression_lvl(level);
+        let threads = threads.max(1);
+        Self {
+            inner,
+            level,
+            threads,
+            compressor: Compressor::new(level),
+            buffer: Vec::with_capacity(BGZF_BLOCK_SIZE * threads),
+            block: Vec::with_capacity(BGZF_MAX_BLOCK_SIZE),
+            blocks: Vec::new(),
+            compressed_offset: 0,
+            uncompressed_offset: 0,
+            finished: false,
+        }
+    }
+
+    fn write_blocks(&mut self) -> std::io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        if self.buffer.len() <= BGZF_BLOCK_SIZE {
+            compress_block(&mut self.compressor, &self.buffer, &mut self.block)?;
+            let block = std::mem::take(&mut self.block);
+            self.write_compressed(&block, self.buffer.len())?;
+            self.block = block;
+        } else {
+            let level = self.level;
+            let blocks = self.buffer
+                .par_chunks(BGZF_BLOCK_SIZE)
+                .map_init(|| (Compressor::new(level), Vec::new()), |(compressor, block), data| {
+                    compress_block(compressor, data, block)?;
+                    Ok((std::mem::take(block), data.len()))
+                })
+                .collect::<std::io::Result<Vec<_>>>()?;
+            for (block, data_len) in blocks {
+                self.write_compressed(&block, data_len)?;
+            }
+        }
+        self.buffer.clear();
+        Ok(())
+    }
+
+    fn write_compressed(&mut self, block: &[u8], data_len: usize) -> std::io::Result<()> {
+        self.inner.write_all(block)?;
+        self.blocks.push((self.compressed_offset, self.uncompressed_offset));
+        self.compressed_offset += block.len() as u64;
+        self.uncompressed_offset += data_len as u64;
+        Ok(())
+    }
+
+    /// Block offsets in `.gzi` order: every block except the first, which
+    /// always starts at (0, 0).
+    pub fn gzi_index(&self) -> &[(u64, u64)] {
+        self.blocks.get(1..).unwrap_or_default()
+    }
+
+    /// Write any pending data and the BGZF EOF marker.
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.write_blocks()?;
+        self.inner.write_all(&BGZF_EOF)?;
+        self.inner.flush()?;
+        self.finished = true;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for BgzfWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let capacity = BGZF_BLOCK_SIZE * self.threads;
+        let mut written = 0;
+        while written < buf.len() {
+            let n = (capacity - self.buffer.len()).min(buf.len() - written);
+            self.buffer.extend_from_slice(&buf[written..written + n]);
+            written += n;
+            if self.buffer.len() == capacity {
+                self.write_blocks()?;
+            }
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.write_blocks()?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for BgzfWriter<W> {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            log::error!("Failed to finish BGZF stream: {}", e);
+        }
+    }
+}
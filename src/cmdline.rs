@@ -1,5 +1,5 @@
 
-use clap::{Parser, Args, Subcommand};
+use clap::{Parser, Args, Subcommand, ValueEnum};
 use std::path::PathBuf;
 
 
@@ -12,9 +12,64 @@ pub struct Cli {
 
 
 #[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
 pub enum Mode {
     #[clap(arg_required_else_help = true, display_order = 1)]
     Fastixe(FastixeArgs),
+    #[clap(arg_required_else_help = true, display_order = 2, about = "Check sequence names against the PanSN spec.")]
+    PansnCheck(PansnCheckArgs),
+    #[clap(arg_required_else_help = true, display_order = 3, visible_alias = "split", about = "Split a PanSN-named FASTA into one file per sample or haplotype.")]
+    Unfastixe(UnfastixeArgs),
+    #[clap(arg_required_else_help = true, display_order = 4, about = "Assembly statistics per genome, sample or haplotype.")]
+    Stats(StatsArgs),
+    #[clap(arg_required_else_help = true, display_order = 5, about = "Index a FASTA or BGZF file, or fetch regions from it like samtools faidx.")]
+    Faidx(FaidxArgs),
+}
+
+#[derive(ValueEnum, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum MergeOrder {
+    /// The order the inputs were given in.
+    #[default]
+    Input,
+    /// Sorted by file name.
+    Name,
+}
+
+#[derive(ValueEnum, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum AlphabetMode {
+    /// Write sequences as they are.
+    #[default]
+    Keep,
+    /// U to T, other IUPAC codes to N, strip spaces and digits, anything
+    /// else to N.
+    Replace,
+    /// Like replace, but fail on anything that is not a nucleotide or IUPAC
+    /// code.
+    Reject,
+}
+
+#[derive(ValueEnum, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum MaskMode {
+    /// Keep soft-masked bases lowercase.
+    #[default]
+    Keep,
+    /// Uppercase all bases, like --up.
+    Upper,
+    /// Replace soft-masked bases with N.
+    Hard,
+    /// Write the soft-masked intervals to <output>.mask.bed, then uppercase.
+    Bed,
+}
+
+#[derive(ValueEnum, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Stop and report every duplicated name.
+    #[default]
+    Fail,
+    /// Append `_1`, `_2`, ... to later occurrences.
+    Suffix,
+    /// Keep the first occurrence and drop the others.
+    Skip,
 }
 
 #[derive(Args, Default, Debug)]
@@ -35,21 +90,66 @@ pub struct FastixeArgs {
     #[clap(short = 'd', long = "input-dir", help_heading = "INPUT FILE", help = "Input directory containing FASTA files.")]
     pub input_directory: Option<PathBuf>,
 
+    #[clap(short = 'S', long = "sample-sheet", help_heading = "INPUT FILE", help = "TSV/CSV sample sheet with a header naming the columns path, sample and optionally haplotype and rename (contig rename table).")]
+    pub sample_sheet: Option<PathBuf>,
+
+    #[clap(long = "strict", overrides_with = "lenient", help_heading = "INPUT FILE", help = "Abort on the first malformed record with its file, record number and byte offset (default).")]
+    pub strict: bool,
+
+    #[clap(long = "lenient", overrides_with = "strict", help_heading = "INPUT FILE", help = "Skip malformed records and report them per file.")]
+    pub lenient: bool,
+
     #[clap(short = 'o', long = "out-dir", default_value = "genomes", help_heading = "OUTPUT", help = "Output directory.")]
     pub out_directory: PathBuf,
 
     #[clap(long = "stdout", help_heading = "SEPARATE OUTPUT", help = "Stdout.")]
     pub is_stdout: bool,
 
-    #[clap(short = 'p', long = "prefix", help_heading = "RENAME", help = "Prefix to add to headers.")]
+    #[clap(short = 'p', long = "prefix", help_heading = "RENAME", help = "Prefix to add to headers, for a single input genome.")]
     pub prefix: Option<String>,
 
-    #[clap(short = 'r', long = "regex", default_value_t = String::from("[^_]+_[^_]+"), help_heading = "RENAME", help = "File name regex")]
+    #[clap(short = 'r', long = "regex", default_value_t = String::from("[^_]+_[^_]+"), help_heading = "RENAME", help = "File name regex. Named groups (?P<sample>...) and (?P<hap>...) set the sample and haplotype; without them the whole match is the sample and the haplotype is taken from hap1/hap2/paternal/maternal in the file name (default 0).")]
     pub reg: String,
 
-    #[clap(short, long="up", help_heading = "Sequence", help = "All bases are converted to uppercase letters.")]
+    #[clap(long = "delimiter", default_value_t = '#', help_heading = "RENAME", help = "PanSN delimiter used to build the prefix.")]
+    pub delimiter: char,
+
+    #[clap(long = "convert-delimiter", value_name = "FROM", help_heading = "RENAME", help = "Names that already are PanSN names with this delimiter are rewritten to use --delimiter instead of getting a prefix.")]
+    pub convert_delimiter: Option<char>,
+
+    #[clap(long = "rename-map", help_heading = "RENAME", help = "Contig rename table (old ID, new ID) or NCBI *_assembly_report.txt, applied before the prefix is added. A sample sheet rename column takes precedence.")]
+    pub rename_map: Option<PathBuf>,
+
+    #[clap(long = "keep-description", help_heading = "RENAME", help = "Keep the header description after the renamed ID.")]
+    pub keep_description: bool,
+
+    #[clap(long = "description-table", help_heading = "RENAME", help = "Write an ID to description TSV (<output>.descriptions.tsv) next to the output.")]
+    pub description_table: bool,
+
+    #[clap(long = "min-length", help_heading = "FILTER", help = "Drop contigs shorter than this.")]
+    pub min_length: Option<u64>,
+
+    #[clap(long = "max-length", help_heading = "FILTER", help = "Drop contigs longer than this.")]
+    pub max_length: Option<u64>,
+
+    #[clap(long = "include-regex", help_heading = "FILTER", help = "Keep only contigs whose ID (after --rename-map) matches this regex.")]
+    pub include_regex: Option<String>,
+
+    #[clap(long = "exclude-regex", help_heading = "FILTER", help = "Drop contigs whose ID (after --rename-map) matches this regex, e.g. 'chrM|chrUn|_random'.")]
+    pub exclude_regex: Option<String>,
+
+    #[clap(short, long="up", help_heading = "Sequence", help = "All bases are converted to uppercase letters, same as --mask-mode upper.")]
     pub uppercase: bool,
 
+    #[clap(long = "mask-mode", value_enum, default_value_t = MaskMode::Keep, conflicts_with = "uppercase", help_heading = "Sequence", help = "What to do with soft-masked (lowercase) bases. The BED uses the PanSN names.")]
+    pub mask_mode: MaskMode,
+
+    #[clap(long = "alphabet", value_enum, default_value_t = AlphabetMode::Keep, help_heading = "Sequence", help = "Check sequence characters and report the changes per genome.")]
+    pub alphabet: AlphabetMode,
+
+    #[clap(long = "line-width", help_heading = "Sequence", help = "Wrap sequences at N bases per line (0 = unwrapped). Keeps the input wrapping if not set.")]
+    pub line_width: Option<usize>,
+
     #[clap(short, long="gz", help_heading = "SEPARATE OUTPUT", help = "Gzip output.")]
     pub gzip_output: bool,
 
@@ -62,10 +162,19 @@ pub struct FastixeArgs {
     #[clap(short = 'b', long="bgz", help_heading = "MERGE OUTPUT", help = "Merge bgzip output.")]
     pub merge_bgzip_output: bool,    
 
+    #[clap(long = "sort", value_enum, default_value_t = MergeOrder::Input, help_heading = "MERGE OUTPUT", help = "Order of the genomes in the merge output.")]
+    pub sort: MergeOrder,
+
+    #[clap(long = "channel-capacity", default_value_t = 4, help_heading = "MERGE OUTPUT", help = "Records buffered per genome while merging; peak memory is about threads x capacity records.")]
+    pub channel_capacity: usize,
+
+    #[clap(long = "on-duplicate", value_enum, default_value_t = DuplicatePolicy::Fail, help_heading = "MERGE OUTPUT", help = "What to do when two records get the same name in the merge output.")]
+    pub on_duplicate: DuplicatePolicy,
+
     #[clap(short, long="faidx", help_heading = "INDEX", help = "Build the index for fasta's bgzip file, just like samtools faidx.")]
     pub faidx: bool,
 
-    #[clap(long = "level", help_heading = "COMPRESSION LEVEL", help = "Compression (0-9).")]
+    #[clap(long = "level", value_parser = clap::value_parser!(u32).range(0..=9), help_heading = "COMPRESSION LEVEL", help = "Compression (0-9).")]
     pub compression_level: Option<u32>,
 
     #[clap(short = 't', long = "threads", default_value_t = 1, help = "Number of threads [default: 1].")]
@@ -75,4 +184,122 @@ pub struct FastixeArgs {
     pub trace: bool,
     #[clap(long="debug", help = "Debug output.")]
     pub debug: bool,
-}
\ No newline at end of file
+}
+#[derive(ValueEnum, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum NameFormat {
+    /// Detect from the file name and content.
+    #[default]
+    Auto,
+    Fasta,
+    Fai,
+    Gfa,
+    Paf,
+}
+
+#[derive(Args, Default, Debug)]
+pub struct PansnCheckArgs {
+    #[clap(required = true, num_args = 1.., help = "FASTA, .fai, GFA or PAF files to check.")]
+    pub input_files: Vec<PathBuf>,
+
+    #[clap(long = "format", value_enum, default_value_t = NameFormat::Auto, help = "Input format.")]
+    pub format: NameFormat,
+
+    #[clap(long = "delimiter", default_value_t = '#', help = "PanSN delimiter.")]
+    pub delimiter: char,
+}
+
+#[derive(ValueEnum, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum SplitBy {
+    /// One file per sample: `HG002.fa`.
+    Sample,
+    /// One file per sample and haplotype: `HG002.hap1.fa`.
+    #[default]
+    Haplotype,
+}
+
+#[derive(Args, Default, Debug)]
+pub struct UnfastixeArgs {
+    #[clap(required = true, help = "PanSN-named FASTA file, e.g. a fastixe merge output (- for stdin).")]
+    pub input_file: PathBuf,
+
+    #[clap(short = 'o', long = "outdir", default_value = ".", help = "Output directory.")]
+    pub out_directory: PathBuf,
+
+    #[clap(long = "by", value_enum, default_value_t = SplitBy::Haplotype, help = "Write one file per sample or per sample and haplotype.")]
+    pub by: SplitBy,
+
+    #[clap(short = 's', long = "strip-prefix", help = "Remove the sample and haplotype prefix from the sequence names (needs --by haplotype).")]
+    pub strip_prefix: bool,
+
+    #[clap(long = "delimiter", default_value_t = '#', help = "PanSN delimiter.")]
+    pub delimiter: char,
+
+    #[clap(short = 'g', long = "gz", help = "Bgzip output.")]
+    pub gzip_output: bool,
+
+    #[clap(long = "level", value_parser = clap::value_parser!(u32).range(0..=9), help = "Compression (0-9).")]
+    pub compression_level: Option<u32>,
+
+    #[clap(short = 't', long = "threads", default_value_t = 1, help = "Number of threads [default: 1].")]
+    pub threads: usize,
+}
+
+#[derive(ValueEnum, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum StatsGroup {
+    /// One row per input file.
+    #[default]
+    File,
+    /// One row per PanSN sample.
+    Sample,
+    /// One row per PanSN sample and haplotype.
+    Haplotype,
+}
+
+#[derive(ValueEnum, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum StatsFormat {
+    #[default]
+    Tsv,
+    Json,
+}
+
+#[derive(Args, Default, Debug)]
+pub struct StatsArgs {
+    #[clap(required = true, num_args = 1.., help = "FASTA files, plain or compressed (- for stdin).")]
+    pub input_files: Vec<PathBuf>,
+
+    #[clap(long = "by", value_enum, default_value_t = StatsGroup::File, help = "Group the sequences by file, or by PanSN sample or haplotype; names that are not PanSN names are counted for their file.")]
+    pub by: StatsGroup,
+
+    #[clap(long = "format", value_enum, default_value_t = StatsFormat::Tsv, help = "Output format.")]
+    pub format: StatsFormat,
+
+    #[clap(short = 'o', long = "output", help = "Output file [default: stdout].")]
+    pub output: Option<PathBuf>,
+
+    #[clap(long = "delimiter", default_value_t = '#', help = "PanSN delimiter.")]
+    pub delimiter: char,
+}
+
+#[derive(Args, Default, Debug)]
+pub struct FaidxArgs {
+    #[clap(required = true, help = "FASTA file, plain or BGZF. Missing .fai/.gzi indexes are built next to it.")]
+    pub input_file: PathBuf,
+
+    #[clap(help = "Regions to fetch: name, name:begin or name:begin-end (1-based, inclusive).")]
+    pub regions: Vec<String>,
+
+    #[clap(short = 'r', long = "region-file", help = "File with one region per line.")]
+    pub region_file: Option<PathBuf>,
+
+    #[clap(long = "bed", help = "BED file of regions (0-based, half-open).")]
+    pub bed: Option<PathBuf>,
+
+    #[clap(short = 'i', long = "reverse-complement", help = "Reverse complement the sequences; '/rc' is appended to the names.")]
+    pub reverse_complement: bool,
+
+    #[clap(short = 'n', long = "length", default_value_t = 60, help = "Bases per output line (0 = unwrapped).")]
+    pub line_width: usize,
+
+    #[clap(short = 'o', long = "output", help = "Output file [default: stdout].")]
+    pub output: Option<PathBuf>,
+}
@@ -0,0 +1,53 @@
+
+use std::fmt;
+
+/// Errors returned by the panutils library. Only the binary turns them
+/// into messages and exit codes.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing a file failed.
+    Io(std::io::Error),
+    /// A user-supplied regular expression does not compile.
+    Regex(regex::Error),
+    /// Invalid or inconsistent command line arguments.
+    Args(String),
+    /// Sequence or file names that cannot be turned into valid PanSN names,
+    /// e.g. a file name not matching `--regex` or duplicate sequence names.
+    Naming(String),
+    /// Malformed input: FASTA, sample sheets, rename tables, indexes.
+    Format(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Regex(e) => write!(f, "Invalid regex: {}", e),
+            Error::Args(msg) | Error::Naming(msg) | Error::Format(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Regex(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<regex::Error> for Error {
+    fn from(e: regex::Error) -> Self {
+        Error::Regex(e)
+    }
+}
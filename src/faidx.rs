@@ -0,0 +1,471 @@
+
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Read, Seek, SeekFrom, Write, stdout};
+
+use crate::cmdline::*;
+use crate::reader::{Compression, sniff_compression};
+
+/// One line of a `.fai` index, as written by `samtools faidx`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FaiRecord {
+    pub name: String,
+    pub length: u64,
+    pub offset: u64,
+    pub line_bases: u64,
+    pub line_width: u64,
+}
+
+#[derive(Default)]
+enum State {
+    #[default]
+    LineStart,
+    Header,
+    Sequence,
+}
+
+/// Streaming FASTA indexer. Feed it the uncompressed bytes of a FASTA file
+/// in order (e.g. while they are being written) and it builds the `.fai`
+/// records without rereading the file.
+#[derive(Default)]
+pub struct FaiBuilder {
+    records: Vec<FaiRecord>,
+    state: State,
+    position: u64,
+    header: Vec<u8>,
+    current: Option<FaiRecord>,
+    line_bases: u64,
+    line_width: u64,
+    short_line: bool,
+}
+
+impl FaiBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        for &byte in buf {
+            match self.state {
+                State::LineStart if byte == b'>' => {
+                    self.finish_record();
+                    self.header.clear();
+                    self.state = State::Header;
+                }
+                State::LineStart | State::Sequence => {
+                    self.state = State::Sequence;
+                    self.line_width += 1;
+                    if byte == b'\n' {
+                        self.finish_line()?;
+                        self.state = State::LineStart;
+                    } else if byte != b'\r' {
+                        self.line_bases += 1;
+                    }
+                }
+                State::Header => {
+                    if byte == b'\n' {
+                        self.start_record()?;
+                        self.state = State::LineStart;
+                    } else {
+                        self.header.push(byte);
+                    }
+                }
+            }
+            self.position += 1;
+        }
+        Ok(())
+    }
+
+    fn start_record(&mut self) -> std::io::Result<()> {
+        let header = String::from_utf8_lossy(&self.header);
+        let name = header.split_whitespace().next().ok_or_else(|| {
+            Error::new(ErrorKind::InvalidData, format!("Missing record id at byte offset {}", self.position))
+        })?;
+        self.current = Some(FaiRecord {
+            name: name.to_string(),
+            length: 0,
+            offset: self.position + 1,
+            line_bases: 0,
+            line_width: 0,
+        });
+        self.short_line = false;
+        Ok(())
+    }
+
+    fn finish_line(&mut self) -> std::io::Result<()> {
+        let (bases, width) = (self.line_bases, self.line_width);
+        self.line_bases = 0;
+        self.line_width = 0;
+
+        let Some(record) = self.current.as_mut() else {
+            if bases > 0 {
+                return Err(Error::new(ErrorKind::InvalidData, "Sequence data found before the first FASTA header"));
+            }
+            return Ok(());
+        };
+        if bases == 0 {
+            self.short_line = true;
+            return Ok(());
+        }
+        if record.line_bases == 0 {
+            record.line_bases = bases;
+            record.line_width = width;
+        } else if self.short_line || bases > record.line_bases || width - bases != record.line_width - record.line_bases {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Different line length in sequence '{}'", record.name),
+            ));
+        } else if bases < record.line_bases {
+            self.short_line = true;
+        }
+        record.length += bases;
+        Ok(())
+    }
+
+    fn finish_record(&mut self) {
+        if let Some(record) = self.current.take() {
+            self.records.push(record);
+        }
+    }
+
+    /// Flush a possibly unterminated last line and return all records.
+    pub fn finish(mut self) -> std::io::Result<Vec<FaiRecord>> {
+        match self.state {
+            State::Header => self.start_record()?,
+            State::Sequence => {
+                // Account for the newline the last line is missing.
+                self.line_width += 1;
+                self.finish_line()?;
+            }
+            State::LineStart => {}
+        }
+        self.finish_record();
+        Ok(self.records)
+    }
+}
+
+/// `merged.gz` -> `merged.gz.fai`, `merged.gz.gzi`, ...
+pub fn index_path(path: &Path, ext: &str) -> PathBuf {
+    let mut index = path.as_os_str().to_owned();
+    index.push(".");
+    index.push(ext);
+    PathBuf::from(index)
+}
+
+pub fn write_fai(path: &Path, records: &[FaiRecord]) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    for record in records {
+        writeln!(writer, "{}\t{}\t{}\t{}\t{}", record.name, record.length, record.offset, record.line_bases, record.line_width)?;
+    }
+    writer.flush()
+}
+
+/// Write a bgzip `.gzi` index: the number of entries followed by
+/// (compressed offset, uncompressed offset) pairs, all little-endian u64.
+pub fn write_gzi(path: &Path, blocks: &[(u64, u64)]) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(&(blocks.len() as u64).to_le_bytes())?;
+    for (compressed_offset, uncompressed_offset) in blocks {
+        writer.write_all(&compressed_offset.to_le_bytes())?;
+        writer.write_all(&uncompressed_offset.to_le_bytes())?;
+    }
+    writer.flush()
+}
+
+pub fn read_fai(path: &Path) -> crate::Result<Vec<FaiRecord>> {
+    let invalid = |line_number: usize| {
+        crate::Error::Format(format!("{}:{}: expected five columns (name, length, offset, line bases, line width)", path.display(), line_number + 1))
+    };
+    let mut records = vec![];
+    for (line_number, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [name, length, offset, line_bases, line_width] = fields[..] else {
+            return Err(invalid(line_number));
+        };
+        let number = |field: &str| field.parse::<u64>().map_err(|_| invalid(line_number));
+        records.push(FaiRecord {
+            name: name.to_string(),
+            length: number(length)?,
+            offset: number(offset)?,
+            line_bases: number(line_bases)?,
+            line_width: number(line_width)?,
+        });
+    }
+    Ok(records)
+}
+
+pub fn read_gzi(path: &Path) -> std::io::Result<Vec<(u64, u64)>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut buf = [0u8; 8];
+    let mut read_u64 = |reader: &mut BufReader<File>| -> std::io::Result<u64> {
+        reader.read_exact(&mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    };
+    let count = read_u64(&mut reader)?;
+    (0..count).map(|_| Ok((read_u64(&mut reader)?, read_u64(&mut reader)?))).collect()
+}
+
+/// The (compressed, uncompressed) offset of every BGZF block after the
+/// first, read from the block headers without decompressing anything.
+pub fn scan_bgzf_blocks(path: &Path) -> crate::Result<Vec<(u64, u64)>> {
+    let not_bgzf = || crate::Error::Format(format!("{} is gzip but not BGZF; recompress it with bgzip", path.display()));
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut blocks = vec![];
+    let (mut compressed_offset, mut uncompressed_offset) = (0u64, 0u64);
+    let mut header = [0u8; 12];
+    loop {
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        // Gzip member with FEXTRA set, carrying the BC subfield.
+        if header[..4] != [0x1f, 0x8b, 0x08, 0x04] {
+            return Err(not_bgzf());
+        }
+        let mut extra = vec![0u8; u16::from_le_bytes([header[10], header[11]]) as usize];
+        reader.read_exact(&mut extra)?;
+        let mut block_size = None;
+        let mut subfields = &extra[..];
+        while subfields.len() >= 4 {
+            let length = u16::from_le_bytes([subfields[2], subfields[3]]) as usize;
+            if subfields[..2] == *b"BC" && length == 2 && subfields.len() >= 6 {
+                block_size = Some(u16::from_le_bytes([subfields[4], subfields[5]]) as u64 + 1);
+            }
+            subfields = &subfields[(4 + length).min(subfields.len())..];
+        }
+        let block_size = block_size.ok_or_else(not_bgzf)?;
+        let rest = block_size - 12 - extra.len() as u64;
+        reader.seek_relative(rest as i64 - 4)?;
+        let mut isize = [0u8; 4];
+        reader.read_exact(&mut isize)?;
+
+        if compressed_offset > 0 {
+            blocks.push((compressed_offset, uncompressed_offset));
+        }
+        compressed_offset += block_size;
+        uncompressed_offset += u32::from_le_bytes(isize) as u64;
+    }
+    // The EOF marker is an empty block that holds no data.
+    if blocks.last().is_some_and(|&(_, uncompressed)| uncompressed == uncompressed_offset) {
+        blocks.pop();
+    }
+    Ok(blocks)
+}
+
+/// A FASTA file (plain or BGZF) with its `.fai` and, if compressed, `.gzi`
+/// index, for fetching regions without reading the whole file.
+pub struct IndexedFasta {
+    file: File,
+    records: HashMap<String, FaiRecord>,
+    // Block offsets including the first block at (0, 0); `None` for
+    // uncompressed files.
+    blocks: Option<Vec<(u64, u64)>>,
+}
+
+impl IndexedFasta {
+    /// Open `path`, building and writing a missing `.fai` or `.gzi` next
+    /// to it like `samtools faidx` does.
+    pub fn open(path: &Path) -> crate::Result<Self> {
+        let mut file = File::open(path)?;
+        let mut magic = [0u8; 4];
+        let n = file.read(&mut magic)?;
+        let blocks = match sniff_compression(&magic[..n]) {
+            Compression::None => None,
+            Compression::Gzip => {
+                let gzi_path = index_path(path, "gzi");
+                let blocks = if gzi_path.exists() {
+                    read_gzi(&gzi_path)?
+                } else {
+                    let blocks = scan_bgzf_blocks(path)?;
+                    write_gzi(&gzi_path, &blocks)?;
+                    blocks
+                };
+                Some([(0, 0)].into_iter().chain(blocks).collect())
+            }
+            _ => return Err(crate::Error::Format(format!("{}: random access needs an uncompressed or BGZF file", path.display()))),
+        };
+
+        let fai_path = index_path(path, "fai");
+        let records = if fai_path.exists() {
+            read_fai(&fai_path)?
+        } else {
+            let mut builder = FaiBuilder::new();
+            let mut reader = crate::reader::open_reader(path)?;
+            loop {
+                let buf = reader.fill_buf()?;
+                if buf.is_empty() {
+                    break;
+                }
+                builder.update(buf)?;
+                let n = buf.len();
+                reader.consume(n);
+            }
+            let records = builder.finish()?;
+            write_fai(&fai_path, &records)?;
+            records
+        };
+
+        Ok(Self {
+            file,
+            records: records.into_iter().map(|r| (r.name.clone(), r)).collect(),
+            blocks,
+        })
+    }
+
+    pub fn record(&self, name: &str) -> Option<&FaiRecord> {
+        self.records.get(name)
+    }
+
+    /// The bases of `name` from `start` to `end` (0-based, half-open),
+    /// clamped to the sequence.
+    pub fn fetch(&mut self, name: &str, start: u64, end: u64) -> crate::Result<Vec<u8>> {
+        let record = self.records.get(name).cloned().ok_or_else(|| {
+            crate::Error::Naming(format!("Sequence '{}' is not in the index", name))
+        })?;
+        let end = end.min(record.length);
+        if start >= end {
+            return Ok(vec![]);
+        }
+        let position = |base: u64| record.offset + base / record.line_bases * record.line_width + base % record.line_bases;
+        let from = position(start);
+        let to = position(end - 1) + 1;
+        let mut bytes = self.read_at(from, to - from)?;
+        bytes.retain(|&b| b != b'\n' && b != b'\r');
+        Ok(bytes)
+    }
+
+    fn read_at(&mut self, offset: u64, length: u64) -> crate::Result<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(length as usize);
+        match &self.blocks {
+            None => {
+                self.file.seek(SeekFrom::Start(offset))?;
+                (&mut self.file).take(length).read_to_end(&mut bytes)?;
+            }
+            Some(blocks) => {
+                // The last block starting at or before `offset`; the blocks
+                // from there on form a multi-member gzip stream.
+                let i = blocks.partition_point(|&(_, uncompressed)| uncompressed <= offset) - 1;
+                let (compressed, uncompressed) = blocks[i];
+                self.file.seek(SeekFrom::Start(compressed))?;
+                let mut decoder = flate2::read::MultiGzDecoder::new(BufReader::new(&mut self.file));
+                std::io::copy(&mut (&mut decoder).take(offset - uncompressed), &mut std::io::sink())?;
+                decoder.take(length).read_to_end(&mut bytes)?;
+            }
+        }
+        if (bytes.len() as u64) < length {
+            return Err(crate::Error::Format("The index does not match the FASTA file".to_string()));
+        }
+        Ok(bytes)
+    }
+}
+
+/// A region to fetch: `start` and `end` are 0-based, half-open; `None`
+/// means the start or end of the sequence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub name: String,
+    pub start: u64,
+    pub end: Option<u64>,
+    // The header to write, as samtools does: the region as given.
+    pub label: String,
+}
+
+/// Parse `name`, `name:begin` or `name:begin-end` (1-based, inclusive,
+/// commas allowed). A whole-string match against `fasta` wins, so names
+/// containing ':' still work.
+pub fn parse_region(region: &str, fasta: &IndexedFasta) -> crate::Result<Region> {
+    let invalid = || crate::Error::Args(format!("Invalid region '{}'", region));
+    let whole = |name: &str| Region { name: name.to_string(), start: 0, end: None, label: region.to_string() };
+    if fasta.record(region).is_some() {
+        return Ok(whole(region));
+    }
+    let Some((name, range)) = region.rsplit_once(':') else {
+        return Ok(whole(region));
+    };
+    let number = |s: &str| s.replace(',', "").parse::<u64>().map_err(|_| invalid());
+    let (start, end) = match range.split_once('-') {
+        Some((start, "")) => (number(start)?, None),
+        Some((start, end)) => (number(start)?, Some(number(end)?)),
+        None => (number(range)?, None),
+    };
+    Ok(Region { name: name.to_string(), start: start.saturating_sub(1), end, label: region.to_string() })
+}
+
+/// BED lines: 0-based, half-open; written as `name:begin-end` (1-based).
+fn parse_bed(path: &Path) -> crate::Result<Vec<Region>> {
+    let mut regions = vec![];
+    for (line_number, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() || line.starts_with('#') || line.starts_with("track") || line.starts_with("browser") {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let invalid = || crate::Error::Format(format!("{}:{}: expected chrom, start and end columns", path.display(), line_number + 1));
+        let [name, start, end, ..] = fields[..] else {
+            return Err(invalid());
+        };
+        let (start, end) = (start.parse::<u64>().map_err(|_| invalid())?, end.parse::<u64>().map_err(|_| invalid())?);
+        regions.push(Region { name: name.to_string(), start, end: Some(end), label: format!("{}:{}-{}", name, start + 1, end) });
+    }
+    Ok(regions)
+}
+
+fn reverse_complement(seq: &mut [u8]) {
+    seq.reverse();
+    for base in seq.iter_mut() {
+        *base = match *base {
+            b'A' => b'T', b'C' => b'G', b'G' => b'C', b'T' => b'A', b'U' => b'A',
+            b'a' => b't', b'c' => b'g', b'g' => b'c', b't' => b'a', b'u' => b'a',
+            b'R' => b'Y', b'Y' => b'R', b'K' => b'M', b'M' => b'K', b'B' => b'V', b'V' => b'B', b'D' => b'H', b'H' => b'D',
+            b'r' => b'y', b'y' => b'r', b'k' => b'm', b'm' => b'k', b'b' => b'v', b'v' => b'b', b'd' => b'h', b'h' => b'd',
+            other => other,
+        };
+    }
+}
+
+pub fn faidx(args: FaidxArgs) -> crate::Result<()> {
+    let mut fasta = IndexedFasta::open(&args.input_file)?;
+
+    let mut regions = vec![];
+    for region in &args.regions {
+        regions.push(parse_region(region, &fasta)?);
+    }
+    if let Some(path) = &args.region_file {
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                regions.push(parse_region(line.trim(), &fasta)?);
+            }
+        }
+    }
+    if let Some(path) = &args.bed {
+        regions.extend(parse_bed(path)?);
+    }
+
+    let mut writer: BufWriter<Box<dyn Write>> = BufWriter::new(match &args.output {
+        Some(path) if path != Path::new("-") => Box::new(File::create(path)?),
+        _ => Box::new(stdout().lock()),
+    });
+    for region in &regions {
+        let mut seq = fasta.fetch(&region.name, region.start, region.end.unwrap_or(u64::MAX))?;
+        if args.reverse_complement {
+            reverse_complement(&mut seq);
+            writeln!(writer, ">{}/rc", region.label)?;
+        } else {
+            writeln!(writer, ">{}", region.label)?;
+        }
+        let width = if args.line_width == 0 { seq.len().max(1) } else { args.line_width };
+        for line in seq.chunks(width) {
+            writer.write_all(line)?;
+            writer.write_all(b"\n")?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
@@ -1,133 +1,79 @@
 
 use std::path::{Path, PathBuf};
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::fs::{read_dir, File, create_dir_all};
-use std::io::{BufReader, BufRead, BufWriter, Write, stdin, stdout};
-use flate2::read::MultiGzDecoder;
+use std::io::{BufReader, BufRead, BufWriter, Read, Write, stdout};
+use std::sync::{Arc, Mutex};
 
 use rayon::prelude::*;
-use crossbeam_channel::unbounded;
-use needletail::parse_fastx_file;
+use crossbeam_channel::{bounded, Receiver, Sender};
+use needletail::errors::ParseErrorKind;
+use needletail::parse_fastx_reader;
 use regex::Regex;
 // use bgzip::write::BGZFMultiThreadWriter;
 use crate::cmdline::*;
+use crate::error::Error;
+use crate::bgzf::BgzfWriter;
+use crate::alphabet::AlphabetNormalizer;
+use crate::filter::ContigFilter;
+use crate::mask::{SoftMasker, write_bed};
+use crate::faidx::{FaiBuilder, index_path, write_fai, write_gzi};
+use crate::reader::{open_reader, strip_compressed_extension};
+use crate::pansn::{haplotype_from_name, parse_haplotype, parse_pansn, pansn_prefix};
+use crate::rename::{ContigRenamer, load_rename_map};
+use crate::sample_sheet::{SampleSheetRecord, parse_sample_sheet};
 use log::*;
 
-#[cfg(not(feature = "c_ffi"))]
-mod not_c_ffi_imports {
-    pub use flate2::write::GzEncoder;
-    pub use flate2::Compression;
-}
-
-#[cfg(not(feature = "c_ffi"))]
-use not_c_ffi_imports::*;
-
 #[cfg(feature = "c_ffi")]
 mod c_ffi_imports {
     pub use rust_htslib::bgzf::{Writer as BGZFWriter, CompressionLevel};
     pub use rust_htslib::tpool::ThreadPool;
-    pub use rust_htslib::faidx::build;
-    pub use libdeflater::{Compressor, CompressionLvl};
 }
 
 #[cfg(feature = "c_ffi")]
 use c_ffi_imports::*;
 
-
-
-#[cfg(feature = "c_ffi")]
-struct GzipDeflaterWriter<W: Write> {
-    inner: W,
-    compressor: Compressor,
-    buffer: Vec<u8>,
-}
-
-#[cfg(feature = "c_ffi")]
-impl<W: Write> GzipDeflaterWriter<W> {
-    pub fn new(inner: W, level: Option<i32>) -> Self {
-        let lvl = level
-            .and_then(|l| CompressionLvl::new(l as i32).ok())
-            .unwrap_or_else(CompressionLvl::default);
-
-        Self {
-            inner,
-            compressor: Compressor::new(lvl),
-            buffer: Vec::new(),
-        }
-    }
-}
-
-#[cfg(feature = "c_ffi")]
-impl<W: Write> Write for GzipDeflaterWriter<W> {
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        let max_size = self.compressor.gzip_compress_bound(buf.len());
-        self.buffer.resize(max_size, 0);
-        let compressed_size = self
-            .compressor
-            .gzip_compress(buf, &mut self.buffer)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-        self.inner.write_all(&self.buffer[..compressed_size])?;
-        Ok(buf.len())
-    }
-
-    fn flush(&mut self) -> std::io::Result<()> {
-        self.inner.flush()
-    }
-}
-
-fn check_args_valid(args: &FastixeArgs) {
-    let level: LevelFilter;
-    if args.trace {
-        level = log::LevelFilter::Trace;
-    } else if args.debug {
-        level = log::LevelFilter::Debug;
-    } else {
-        level = log::LevelFilter::Info
-    }
-
-    simple_logger::SimpleLogger::new()
-        .with_level(level)
-        .init()
-        .unwrap();
-
-    rayon::ThreadPoolBuilder::new().num_threads(args.threads).build_global().unwrap();
-
+fn check_args_valid(args: &FastixeArgs) -> crate::Result<()> {
     if args.input_genome.is_none()
         && args.input_list.is_none()
         && args.input_directory.is_none()
         && args.input_files.is_none()
+        && args.sample_sheet.is_none()
     {
         if args.input_stdin.is_none() {
-            error!("No genome found! Exiting.");
-            std::process::exit(1); 
+            return Err(Error::Args("No genome found!".to_string()));
         }  
     } else {
         if args.input_stdin.is_some() {
-            error!("Input stream option --stdin cannot be shared with other input options.");
-            std::process::exit(1);            
+            return Err(Error::Args("Input stream option --stdin cannot be shared with other input options.".to_string()));
         }    
     }
 
     if args.input_stdin.is_some() && args.prefix.is_none() {
-        error!("Input stream option --stdin need provide prefix.");
-        std::process::exit(1);         
+        return Err(Error::Args("Input stream option --stdin need provide prefix.".to_string()));
+    }
+
+    if args.compression_level.is_some_and(|level| level > 9) {
+        return Err(Error::Args("The compression level must be between 0 and 9.".to_string()));
     }
 
     // if args.prefix.is_none() {
     //     warn!("No prefix provided; use default regex.");
     // }
+    Ok(())
 }
 
-fn parse_line_file(path: &Path, vec: &mut Vec<String>) -> std::io::Result<()> {
+fn parse_line_file(path: &Path, vec: &mut Vec<String>) -> crate::Result<()> {
     let file = File::open(path)?;
     let reader = BufReader::new(file);
     for line in reader.lines() {
         let line = line?;
-        let path: &Path = line.as_ref();
-        if path.exists() {
-            vec.push(line);
-        } else {
-            eprintln!("{:?} does not exist!", path);
+        let genome: &Path = line.as_ref();
+        if !genome.exists() {
+            return Err(Error::Args(format!("{:?} listed in {:?} does not exist", genome, path)));
         }
+        vec.push(line);
     }
     Ok(())
 }
@@ -136,20 +82,30 @@ fn is_fasta<P: AsRef<Path>>(file: P) -> bool {
     let path = file.as_ref();
 
     if let Some(file_name) = path.to_str() {
+        let file_name = strip_compressed_extension(file_name);
         file_name.ends_with(".fa") ||
             file_name.ends_with(".fna") ||
-            file_name.ends_with(".fasta") ||
-            file_name.ends_with(".fa.gz") ||
-            file_name.ends_with(".fna.gz") ||
-            file_name.ends_with(".fasta.gz")
+            file_name.ends_with(".fasta")
     } else {
-        eprintln!("{:?} can not convert to utf-8 string", path);
+        warn!("Skipping {:?}: the path is not valid UTF-8", path);
         false
     }
 
 }
 
-fn parse_files(args: &FastixeArgs, input_genomes: &mut Vec<String>) {
+// An input genome; genomes from a sample sheet carry their own PanSN naming.
+struct InputGenome {
+    path: String,
+    sample_sheet_record: Option<SampleSheetRecord>,
+}
+
+impl From<String> for InputGenome {
+    fn from(path: String) -> Self {
+        Self { path, sample_sheet_record: None }
+    }
+}
+
+fn parse_files(args: &FastixeArgs, input_genomes: &mut Vec<InputGenome>) -> crate::Result<()> {
     let mut all_files = vec![];
 
     if let Some(ref input_stdin) = args.input_stdin {
@@ -157,170 +113,501 @@ fn parse_files(args: &FastixeArgs, input_genomes: &mut Vec<String>) {
     }
 
     if let Some(ref input_genome) = args.input_genome {
-        if input_genome.exists() {
-            all_files.push(input_genome.to_string_lossy().to_string());
-        } else {
-            eprintln!("{:?} does not exist!", input_genome)
+        if !input_genome.exists() {
+            return Err(Error::Args(format!("{:?} does not exist", input_genome)));
         }
+        all_files.push(input_genome.to_string_lossy().to_string());
     }
 
     if let Some(ref input_files) = args.input_files {
         for input_file in input_files {
-            if input_file.is_file() && is_fasta(&input_file) {
-                all_files.push(input_file.to_string_lossy().to_string());
+            if !input_file.is_file() {
+                return Err(Error::Args(format!("{:?} does not exist or is not a file", input_file)));
             }
+            if !is_fasta(input_file) {
+                return Err(Error::Args(format!("{:?} is not a FASTA file (.fa, .fna or .fasta, optionally compressed)", input_file)));
+            }
+            all_files.push(input_file.to_string_lossy().to_string());
         }
     }
 
     if let Some(ref input_list) = args.input_list {
-        if input_list.exists() {
-            parse_line_file(&input_list, &mut all_files).unwrap();
+        if !input_list.is_file() {
+            return Err(Error::Args(format!("{:?} does not exist or is not a file", input_list)));
         }
+        parse_line_file(input_list, &mut all_files)?;
     }
 
     if let Some(ref input_directory) = args.input_directory {
-        if input_directory.exists() && input_directory.is_dir() {
-            for entry in read_dir(&input_directory).unwrap() {
-                let entry = entry.unwrap();
-                let path = entry.path();
-                if path.is_file() && is_fasta(&path) {
-                    all_files.push(path.to_string_lossy().to_string());
-                }
+        if !input_directory.is_dir() {
+            return Err(Error::Args(format!("{:?} does not exist or is not a directory", input_directory)));
+        }
+        // Directory listings come back in file system order; sort them so
+        // the input order is reproducible.
+        let mut paths = vec![];
+        for entry in read_dir(input_directory)? {
+            let path = entry?.path();
+            if path.is_file() && is_fasta(&path) {
+                paths.push(path);
             }
         }
+        paths.sort();
+        all_files.extend(paths.iter().map(|path| path.to_string_lossy().to_string()));
     }
 
+    let mut all_genomes: Vec<InputGenome> = all_files.into_iter().map(InputGenome::from).collect();
 
-    input_genomes.extend(all_files);
+    if let Some(ref sample_sheet) = args.sample_sheet {
+        for record in parse_sample_sheet(sample_sheet)? {
+            all_genomes.push(InputGenome {
+                path: record.path.to_string_lossy().to_string(),
+                sample_sheet_record: Some(record),
+            });
+        }
+    }
 
+    if args.sort == MergeOrder::Name {
+        all_genomes.sort_by(|a, b| Path::new(&a.path).file_name().cmp(&Path::new(&b.path).file_name()));
+    }
+
+    if all_genomes.is_empty() {
+        return Err(Error::Args("No genome found!".to_string()));
+    }
+    // The same prefix on every genome would only give duplicate names.
+    if args.prefix.is_some() && all_genomes.len() > 1 {
+        return Err(Error::Args(format!("--prefix needs a single input genome, found {}; use --regex or a sample sheet", all_genomes.len())));
+    }
+    input_genomes.extend(all_genomes);
+    Ok(())
 }
 
-fn open_reader(file_path: &Path) -> std::io::Result<Box<dyn BufRead>> {
+fn split_header(header: &str) -> Option<(&str, &str)> {
+    let header = header.trim();
+    let id = header.split_whitespace().next()?;
+    Some((id, header[id.len()..].trim_start()))
+}
 
-    if file_path == Path::new("-") {
-        let stdin = stdin();
-        let handle = stdin.lock();
-        let reader: Box<dyn BufRead> = Box::new(BufReader::new(handle));
-        Ok(reader)
-    } else {
-        let input_file = File::open(file_path)?;
-        let reader: Box<dyn BufRead> = if file_path.extension().map_or(false, |e| e == ".gz") {
-            Box::new(BufReader::new(MultiGzDecoder::new(input_file)))
-        } else {
-            Box::new(BufReader::new(input_file))
-        };        
-        Ok(reader)
-    }
-}
-
-fn process_fasta(file_path: &Path, output_file_path: &Path, prefix: &str, uppercase: bool, gzip_output: bool, compression_level: Option<u32>, is_stdout: bool) -> std::io::Result<()> {
-    let reader = open_reader(file_path)?;
-    // let compression = compression_level
-    //     .map(Compression::new)
-    //     .unwrap_or(Compression::default());
-    // let writer: Box<dyn Write> = if is_stdout || output_file_path == Path::new("-") {
-    //     let stdout = stdout();
-    //     if gzip_output {
-    //         let handle = stdout.lock();
-    //         Box::new(GzEncoder::new(handle, compression))            
-    //     } else {
-    //         Box::new(stdout.lock())
-    //     }
-    // } else {
-    //     let output_file = File::create(output_file_path)?;
-    //     if gzip_output {
-    //         Box::new(GzEncoder::new(output_file, compression))
-    //     } else {
-    //         Box::new(output_file)
-    //     }
-    // };
-
-    let writer: Box<dyn Write> = if is_stdout || output_file_path == Path::new("-") {
-        let handle = stdout().lock();
-
-        if gzip_output {
-            #[cfg(not(feature = "c_ffi"))]
-            {
-                let compression = compression_level.map(Compression::new).unwrap_or(Compression::default());
-                Box::new(GzEncoder::new(handle, compression))
-            }
+fn report_filtered(file_path: &Path, filter: &ContigFilter) {
+    if filter.is_active() {
+        let counts = filter.counts();
+        info!(
+            "{:?}: kept {} contigs, dropped {} by name and {} by length ({} bp)",
+            file_path, counts.kept, counts.dropped_by_name, counts.dropped_by_length, counts.dropped_bases
+        );
+    }
+}
 
-            #[cfg(feature = "c_ffi")]
-            {
-                Box::new(GzipDeflaterWriter::new(handle, compression_level.map(|v| v as i32)))
+fn report_unmapped(file_path: &Path, renamer: &ContigRenamer) {
+    let unmapped = renamer.unmapped();
+    if !unmapped.is_empty() {
+        let shown = unmapped.iter().take(5).map(String::as_str).collect::<Vec<_>>().join(", ");
+        let more = if unmapped.len() > 5 { ", ..." } else { "" };
+        warn!("{} contigs in {:?} are not in the rename map and keep their IDs: {}{}", unmapped.len(), file_path, shown, more);
+    }
+}
+
+/// Malformed records of one input file. The first one is an error, unless
+/// `--lenient` is set: then they are skipped and summarised once the file
+/// is done.
+struct MalformedRecords<'a> {
+    file_path: &'a Path,
+    lenient: bool,
+    errors: Vec<String>,
+}
+
+impl<'a> MalformedRecords<'a> {
+    fn new(file_path: &'a Path, lenient: bool) -> Self {
+        Self { file_path, lenient, errors: vec![] }
+    }
+
+    /// `record` counts from 1 and `offset` is the byte offset of the record
+    /// in the decompressed input.
+    fn report(&mut self, record: u64, offset: u64, reason: &str) -> crate::Result<()> {
+        let message = format!("record {} at byte offset {}: {}", record, offset, reason);
+        if !self.lenient {
+            return Err(Error::Format(format!("{:?}: {} (use --lenient to skip malformed records)", self.file_path, message)));
+        }
+        self.errors.push(message);
+        Ok(())
+    }
+
+    fn summary(&self) {
+        if self.errors.is_empty() {
+            return;
+        }
+        warn!("{:?}: skipped {} malformed records", self.file_path, self.errors.len());
+        for message in self.errors.iter().take(5) {
+            warn!("{:?}: {}", self.file_path, message);
+        }
+        if self.errors.len() > 5 {
+            warn!("{:?}: ... and {} more", self.file_path, self.errors.len() - 5);
+        }
+    }
+}
+
+fn report_alphabet(file_path: &Path, alphabet: &AlphabetNormalizer) {
+    if alphabet.is_active() {
+        let counts = alphabet.counts();
+        info!(
+            "{:?}: converted {} U to T and {} ambiguity codes to N, replaced {} invalid characters with N, stripped {} spaces and digits",
+            file_path, counts.u_to_t, counts.ambiguous, counts.replaced, counts.stripped
+        );
+    }
+}
+
+fn invalid_base(file_path: &Path, record: u64, base: u8) -> Error {
+    Error::Format(format!(
+        "{:?}: record {} contains '{}', which is not a nucleotide or IUPAC code (see --alphabet)",
+        file_path, record, base.escape_ascii()
+    ))
+}
+
+/// What is done to the records of every genome, taken from the arguments
+/// once.
+struct TransformOptions<'a> {
+    rename_map: Option<&'a HashMap<String, String>>,
+    filter: &'a ContigFilter,
+    alphabet: AlphabetMode,
+    mask_mode: MaskMode,
+    lenient: bool,
+    // (from, to), see `record_name`.
+    convert_delimiter: Option<(char, char)>,
+    line_width: Option<usize>,
+    keep_description: bool,
+}
+
+impl<'a> TransformOptions<'a> {
+    fn new(args: &FastixeArgs, rename_map: Option<&'a HashMap<String, String>>, filter: &'a ContigFilter) -> Self {
+        Self {
+            rename_map,
+            filter,
+            alphabet: args.alphabet,
+            mask_mode: if args.uppercase { MaskMode::Upper } else { args.mask_mode },
+            lenient: args.lenient,
+            convert_delimiter: args.convert_delimiter.map(|from| (from, args.delimiter)),
+            line_width: args.line_width,
+            keep_description: args.keep_description,
+        }
+    }
+
+    fn genome<'g>(&'g self, genome: &'g InputGenome, prefix: String) -> GenomeTransform<'g> {
+        let file_path = genome.path.as_ref();
+        GenomeTransform {
+            file_path,
+            prefix,
+            renamer: ContigRenamer::new(genome.sample_sheet_record.as_ref().and_then(|r| r.contig_names.as_ref()), self.rename_map),
+            filter: self.filter.clone(),
+            alphabet: AlphabetNormalizer::new(self.alphabet),
+            masker: SoftMasker::new(self.mask_mode),
+            malformed: MalformedRecords::new(file_path, self.lenient),
+            options: self,
+        }
+    }
+}
+
+/// The transforms of one genome, which count what they did to it.
+struct GenomeTransform<'a> {
+    file_path: &'a Path,
+    prefix: String,
+    renamer: ContigRenamer<'a>,
+    filter: ContigFilter,
+    alphabet: AlphabetNormalizer,
+    masker: SoftMasker,
+    malformed: MalformedRecords<'a>,
+    options: &'a TransformOptions<'a>,
+}
+
+impl GenomeTransform<'_> {
+    fn name(&self, record_id: &str) -> String {
+        record_name(&self.prefix, record_id, self.options.convert_delimiter)
+    }
+
+    // The end of a record: pass it on if the filter keeps it.
+    fn finish_record<W: Write>(&mut self, writer: &mut W, record: &mut Vec<u8>, kept: Option<(String, String)>, length: u64, description_table: Option<&mut (dyn Write + '_)>, mask_bed: Option<&mut (dyn Write + '_)>) -> std::io::Result<()> {
+        let intervals = self.masker.finish_record();
+        if self.filter.record(kept.is_some(), length) {
+            writer.write_all(record)?;
+            if let (Some(bed), Some((name, _))) = (mask_bed, &kept) {
+                write_bed(bed, name, &intervals)?;
+            }
+            if let (Some(table), Some((name, description))) = (description_table, kept) {
+                writeln!(table, "{}\t{}", name, description)?;
             }
-        } else {
-            Box::new(handle)
         }
+        record.clear();
+        Ok(())
+    }
+
+    fn report(&self) {
+        report_unmapped(self.file_path, &self.renamer);
+        report_filtered(self.file_path, &self.filter);
+        report_alphabet(self.file_path, &self.alphabet);
+        self.malformed.summary();
+    }
+}
+
+// The output name of a record: the prefix plus the id, or with
+// `convert_delimiter` = (from, to), an id that already is a PanSN name with
+// `from` re-joined with `to` and no prefix added.
+fn record_name(prefix: &str, record_id: &str, convert_delimiter: Option<(char, char)>) -> String {
+    convert_delimiter
+        .and_then(|(from, to)| parse_pansn(record_id, from).map(|pansn| pansn.join(to)))
+        .unwrap_or_else(|| format!("{}{}", prefix, record_id))
+}
+
+fn write_header<W: Write>(writer: &mut W, name: &str, description: &str, keep_description: bool) -> std::io::Result<()> {
+    if keep_description && !description.is_empty() {
+        writeln!(writer, ">{} {}", name, description)
     } else {
-        let output_file = File::create(output_file_path)?;
-        if gzip_output {
-            #[cfg(not(feature = "c_ffi"))]
-            {
-                let compression = compression_level.map(Compression::new).unwrap_or(Compression::default());
-                Box::new(GzEncoder::new(output_file, compression))
+        writeln!(writer, ">{}", name)
+    }
+}
+
+// Re-wraps sequence data fed to it in arbitrary pieces at `width` bases per
+// line; a width of 0 writes each sequence on a single line.
+struct LineWrapper {
+    width: usize,
+    column: usize,
+}
+
+impl LineWrapper {
+    fn new(width: usize) -> Self {
+        Self { width, column: 0 }
+    }
+
+    fn write<W: Write>(&mut self, writer: &mut W, mut seq: &[u8]) -> std::io::Result<()> {
+        if self.width == 0 {
+            self.column += seq.len();
+            return writer.write_all(seq);
+        }
+        while !seq.is_empty() {
+            if self.column == self.width {
+                writer.write_all(b"\n")?;
+                self.column = 0;
             }
+            let n = (self.width - self.column).min(seq.len());
+            writer.write_all(&seq[..n])?;
+            self.column += n;
+            seq = &seq[n..];
+        }
+        Ok(())
+    }
 
-            #[cfg(feature = "c_ffi")]
-            {
-                Box::new(GzipDeflaterWriter::new(output_file, compression_level.map(|v| v as i32)))
+    fn end_record<W: Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        if self.column > 0 {
+            writer.write_all(b"\n")?;
+        }
+        self.column = 0;
+        Ok(())
+    }
+}
+
+fn rename_fasta<W: Write>(mut reader: impl BufRead, writer: &mut W, transform: &mut GenomeTransform, mut description_table: Option<&mut dyn Write>, mut mask_bed: Option<&mut dyn Write>) -> crate::Result<()> {
+    let mut wrapper = transform.options.line_width.map(LineWrapper::new);
+    // Output goes through `record`: line by line, or, with a length filter
+    // or --lenient, once the record is complete, so that a record cut off by
+    // a read error can be dropped.
+    let hold_records = transform.filter.has_length_bounds() || transform.options.lenient;
+    let mut truncated = false;
+    let mut record = vec![];
+    let mut open = false;
+    // Lines up to the next header are dropped after a malformed one.
+    let mut skipping = false;
+    // Name and description of the open record, unless its ID was filtered.
+    let mut kept: Option<(String, String)> = None;
+    let mut length = 0;
+    let mut records = 0;
+    let mut record_offset = 0;
+    let mut offset = 0;
+    let mut line = vec![];
+    let mut normalized = vec![];
+    loop {
+        line.clear();
+        let line_offset = offset;
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) => break,
+            Ok(n) => offset += n as u64,
+            // E.g. a truncated download; nothing after it can be read. The
+            // open record is dropped, unless the cut is in the next header.
+            Err(e) => {
+                let next_header = line.starts_with(b">");
+                if next_header {
+                    transform.malformed.report(records + 1, line_offset, &e.to_string())?;
+                } else {
+                    transform.malformed.report(records.max(1), record_offset, &e.to_string())?;
+                }
+                truncated = !next_header;
+                break;
             }
-        } else {
-            Box::new(output_file)
         }
-    };
-    let mut writer = BufWriter::new(writer);
-    
-    for line in reader.lines() {
-        let line = line?;
-        if line.starts_with(">") {
-            if let Some(record_id) = line[1..].split_whitespace().next() {
-                writeln!(writer, ">{}{}", prefix, record_id)?;
-            } else {
-                eprintln!("Missing recored id in file: {:?}", file_path);
+        let content = line.strip_suffix(b"\n").unwrap_or(&line);
+        let content = content.strip_suffix(b"\r").unwrap_or(content);
+        if let Some(header) = content.strip_prefix(b">") {
+            if let Some(wrapper) = wrapper.as_mut() {
+                wrapper.end_record(&mut record)?;
             }
-        } else {
-            if uppercase {
-                writeln!(writer, "{}", line.to_ascii_uppercase())?;
+            if open {
+                transform.finish_record(writer, &mut record, kept.take(), length, description_table.as_deref_mut(), mask_bed.as_deref_mut())?;
+            }
+            open = false;
+            skipping = false;
+            length = 0;
+            records += 1;
+            record_offset = line_offset;
+            match std::str::from_utf8(header).map(split_header) {
+                Ok(Some((record_id, description))) => {
+                    let record_id = transform.renamer.rename(record_id);
+                    open = true;
+                    if transform.filter.keep_name(record_id) {
+                        let name = transform.name(record_id);
+                        write_header(&mut record, &name, description, transform.options.keep_description)?;
+                        kept = Some((name, description.to_string()));
+                    }
+                }
+                Ok(None) => {
+                    transform.malformed.report(records, record_offset, "header without a record ID")?;
+                    skipping = true;
+                }
+                Err(_) => {
+                    transform.malformed.report(records, record_offset, "header is not valid UTF-8")?;
+                    skipping = true;
+                }
+            }
+        } else if open {
+            let content = if kept.is_some() && transform.alphabet.is_active() {
+                normalized.clear();
+                transform.alphabet.normalize(content, &mut normalized).map_err(|base| invalid_base(transform.file_path, records, base))?;
+                // Lines of only digits and spaces are dropped.
+                if normalized.is_empty() && !content.is_empty() {
+                    continue;
+                }
+                &normalized[..]
             } else {
-                writeln!(writer, "{}", line)?;
+                content
+            };
+            length += content.trim_ascii_end().len() as u64;
+            if kept.is_some() {
+                let content = if transform.masker.is_active() {
+                    let mut content = content.to_vec();
+                    transform.masker.apply(&mut content);
+                    Cow::from(content)
+                } else {
+                    Cow::from(content)
+                };
+                if let Some(wrapper) = wrapper.as_mut() {
+                    wrapper.write(&mut record, content.trim_ascii_end())?;
+                } else {
+                    record.write_all(&content)?;
+                    record.write_all(b"\n")?;
+                }
             }
-            
+        } else if !skipping && !content.trim_ascii().is_empty() {
+            transform.malformed.report(1, line_offset, "sequence data before the first header")?;
+            skipping = true;
+        }
+        if !hold_records {
+            writer.write_all(&record)?;
+            record.clear();
+        }
+    }
+    if let Some(wrapper) = wrapper.as_mut() {
+        wrapper.end_record(&mut record)?;
+    }
+    if open && !truncated {
+        transform.finish_record(writer, &mut record, kept.take(), length, description_table, mask_bed)?;
+    }
+    Ok(())
+}
+
+fn process_fasta(args: &FastixeArgs, mut transform: GenomeTransform, output_file_path: &Path) -> crate::Result<()> {
+    let reader = open_reader(transform.file_path)?;
+
+    let to_stdout = args.is_stdout || output_file_path == Path::new("-");
+    let output: Box<dyn Write> = if to_stdout {
+        Box::new(stdout().lock())
+    } else {
+        Box::new(File::create(output_file_path)?)
+    };
+
+    let mut table = if args.description_table && !to_stdout {
+        Some(BufWriter::new(File::create(index_path(output_file_path, "descriptions.tsv"))?))
+    } else {
+        if args.description_table {
+            warn!("--description-table is ignored for stdout output.");
+        }
+        None
+    };
+    let mut mask_bed = if transform.masker.writes_bed() && !to_stdout {
+        Some(BufWriter::new(File::create(index_path(output_file_path, "mask.bed"))?))
+    } else {
+        if transform.masker.writes_bed() {
+            warn!("The --mask-mode bed intervals are not written for stdout output.");
         }
+        None
+    };
+
+    // Gzip output is written as BGZF: a valid gzip file made of 64 KiB
+    // blocks, which are compressed in parallel.
+    if args.gzip_output {
+        let mut writer = BgzfWriter::new(output, args.compression_level, args.threads);
+        rename_fasta(reader, &mut writer, &mut transform, table.as_mut().map(|t| t as &mut dyn Write), mask_bed.as_mut().map(|b| b as &mut dyn Write))?;
+        writer.finish()?;
+    } else {
+        let mut writer = BufWriter::new(output);
+        rename_fasta(reader, &mut writer, &mut transform, table.as_mut().map(|t| t as &mut dyn Write), mask_bed.as_mut().map(|b| b as &mut dyn Write))?;
+        writer.flush()?;
+    }
+    if let Some(mut table) = table {
+        table.flush()?;
+    }
+    if let Some(mut mask_bed) = mask_bed {
+        mask_bed.flush()?;
     }
+    transform.report();
     Ok(())
 }
 
 
 
-fn make_output_path(input_file_path: &Path, output_dir_path: &Path, gzip_output: bool) -> PathBuf {
+fn make_output_path(input_file_path: &Path, output_dir_path: &Path, gzip_output: bool) -> crate::Result<PathBuf> {
     // let input_file_stem = input_file_path.file_stem().unwrap().to_string_lossy();
     // let ext = if gzip_output {"fa.gz"} else {"fa"};
     // output_dir_path.join(format!("{input_file_stem}.{ext}"))
-    let input_file_name = input_file_path.file_name().unwrap().to_string_lossy();
+    let input_file_name = input_file_path.file_name()
+        .ok_or_else(|| Error::Naming(format!("Invalid file path {:?}", input_file_path)))?
+        .to_string_lossy();
+    // Compressed inputs are decompressed, so drop their extension.
+    let input_file_name = strip_compressed_extension(&input_file_name);
     if gzip_output {
-        output_dir_path.join(format!("{input_file_name}.gz"))
+        Ok(output_dir_path.join(format!("{input_file_name}.gz")))
     } else {
-        output_dir_path.join(format!("{input_file_name}"))
+        Ok(output_dir_path.join(input_file_name))
     }
 }
 
 
-fn extract_prefix_from_path(file_path: &Path, regex: &str) -> Result<String, std::io::Error> {
+fn extract_prefix_from_path(file_path: &Path, regex: &str, delimiter: char) -> crate::Result<String> {
     let input_file_name = file_path.file_name()
-        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid file path"))?
+        .ok_or_else(|| Error::Naming(format!("Invalid file path {:?}", file_path)))?
         .to_string_lossy();
 
-    let re = Regex::new(regex)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Invalid regex: {}", e)))?;
+    let re = Regex::new(regex)?;
 
     match re.captures(&input_file_name) {
         Some(caps) => {
-            let matched = caps.get(0).map(|m| m.as_str()).unwrap_or("");
-            Ok(format!("{}#0#", matched))
+            // Named groups `sample` and `hap` take precedence; otherwise the
+            // whole match is the sample and the haplotype is recognised from
+            // the file name (hap1, paternal, ...), defaulting to 0.
+            let sample = caps.name("sample").or_else(|| caps.get(0)).map(|m| m.as_str()).unwrap_or("");
+            let hap = match caps.name("hap") {
+                Some(hap) => parse_haplotype(hap.as_str()).ok_or_else(|| Error::Naming(
+                    format!("Haplotype '{}' in filename '{}' is not an integer", hap.as_str(), input_file_name),
+                ))?,
+                None => haplotype_from_name(&input_file_name).unwrap_or(0),
+            };
+            Ok(pansn_prefix(sample, &hap.to_string(), delimiter))
         }
-        None => Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidInput,
+        None => Err(Error::Naming(
             format!(
                 "Filename '{}' doesn't match regex '{}'",
                 input_file_name, regex
@@ -329,155 +616,696 @@ fn extract_prefix_from_path(file_path: &Path, regex: &str) -> Result<String, std
     }
 }
 
-fn process_fasta_needle(file_path: &Path, regex: &str, uppercase: bool) -> Result<Vec<String>, std::io::Error> {
-    let mut results = vec![];
-    
-    // let input_file_name = file_path.file_name().unwrap().to_string_lossy();
-    // let re = Regex::new(regex).map_err(|e| {
-    //     std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Invalid regex: {}", e))
-    // })?;
-    // let prefix = match re.captures(&input_file_name) {
-    //     Some(caps) => {
-    //         let matched = caps.get(0).map(|m| m.as_str()).unwrap_or("");
-    //         format!("{}#0#", matched)
-    //     }
-    //     None => {
-    //         return Err(std::io::Error::new(
-    //             std::io::ErrorKind::InvalidInput,
-    //             format!(
-    //                 "Filename '{}' doesn't match regex '{}'",
-    //                 input_file_name, regex
-    //             ),
-    //         ));
-    //     }
-    // };
-    let prefix = extract_prefix_from_path(file_path, regex)?;
-
-    if let Ok(mut reader) = parse_fastx_file(file_path) {
-        while let Some(record) = reader.next() {
-            if let Ok(seqrec) = record {
-                if let Some(first_record_id) = std::str::from_utf8(seqrec.id()).unwrap().split_whitespace().next() {
-                    if let Ok(seq) = std::str::from_utf8(seqrec.raw_seq()) {
-                        let seq_formatted = if uppercase {
-                            seq.to_ascii_uppercase()
-                        } else {
-                            seq.to_string()
-                        };
-                        results.push(format!(">{}{}\n{}\n", prefix, first_record_id, seq_formatted));
-                    } else {
-                        eprintln!("Invalid UTF-8 sequence in file: {:?}", file_path);
+fn genome_prefix(args: &FastixeArgs, genome: &InputGenome) -> crate::Result<String> {
+    match &genome.sample_sheet_record {
+        Some(record) => Ok(pansn_prefix(&record.sample, &record.haplotype.to_string(), args.delimiter)),
+        None => match &args.prefix {
+            Some(prefix) => Ok(prefix.clone()),
+            None => extract_prefix_from_path(genome.path.as_ref(), args.reg.as_ref(), args.delimiter),
+        },
+    }
+}
+
+// A renamed record on its way to the merge writer.
+struct MergeRecord {
+    fasta: Vec<u8>,
+    name: String,
+    description: String,
+    // Soft-masked intervals for --mask-mode bed.
+    mask: Vec<(u64, u64)>,
+}
+
+impl MergeRecord {
+    /// Replace the name in both the header and `name`.
+    fn rename(&mut self, name: String) {
+        // The header always starts with '>' followed by the name.
+        self.fasta.splice(1..1 + self.name.len(), name.bytes());
+        self.name = name;
+    }
+}
+
+/// Names already written to the merge output and the genome they came from.
+#[derive(Default)]
+struct DuplicateTracker {
+    seen: HashMap<String, usize>,
+    // (name, first genome, duplicate genome)
+    duplicates: Vec<(String, usize, usize)>,
+}
+
+impl DuplicateTracker {
+    /// Apply `policy` to `record` from genome `index`; returns whether it
+    /// should be written.
+    fn check(&mut self, record: &mut MergeRecord, index: usize, policy: DuplicatePolicy) -> bool {
+        let Some(&first) = self.seen.get(&record.name) else {
+            self.seen.insert(record.name.clone(), index);
+            return true;
+        };
+        match policy {
+            DuplicatePolicy::Fail => {
+                self.duplicates.push((record.name.clone(), first, index));
+                false
+            }
+            DuplicatePolicy::Skip => {
+                warn!("Skipping duplicate sequence name '{}'", record.name);
+                false
+            }
+            DuplicatePolicy::Suffix => {
+                let name = (1..)
+                    .map(|n| format!("{}_{}", record.name, n))
+                    .find(|name| !self.seen.contains_key(name))
+                    .unwrap();
+                warn!("Renaming duplicate sequence name '{}' to '{}'", record.name, name);
+                record.rename(name);
+                self.seen.insert(record.name.clone(), index);
+                true
+            }
+        }
+    }
+}
+
+// Ends the stream at the first read error and keeps the error, so that the
+// parser still hands out every record before it.
+struct StopAtReadError<R> {
+    inner: R,
+    error: Arc<Mutex<Option<std::io::Error>>>,
+}
+
+impl<R: Read> Read for StopAtReadError<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.inner.read(buf) {
+            Err(e) if e.kind() != std::io::ErrorKind::Interrupted => {
+                *self.error.lock().unwrap() = Some(e);
+                Ok(0)
+            }
+            result => result,
+        }
+    }
+}
+
+// Records are sent one at a time, so a genome is never held in memory as a
+// whole; this returns early once the receiver has gone away.
+fn process_fasta_needle(mut transform: GenomeTransform, sender: &Sender<crate::Result<MergeRecord>>) -> crate::Result<()> {
+    let file_path = transform.file_path;
+    let line_width = transform.options.line_width;
+    let read_error = Arc::new(Mutex::new(None));
+    let input = StopAtReadError { inner: open_reader(file_path)?, error: Arc::clone(&read_error) };
+    // A record is sent once the next one has been read: after a read error
+    // the last one may be incomplete and is dropped, as with separate output.
+    let mut pending: Option<MergeRecord> = None;
+    let mut records = 0;
+    let mut offset = 0;
+    match parse_fastx_reader(input) {
+        Ok(mut reader) => {
+            while let Some(record) = reader.next() {
+                records += 1;
+                if let Some(previous) = pending.take()
+                    && sender.send(Ok(previous)).is_err()
+                {
+                    return Ok(());
+                }
+                let seqrec = match record {
+                    Ok(seqrec) => seqrec,
+                    // The parser cannot recover, so the rest of the file is
+                    // lost. A read error is reported below.
+                    Err(e) => {
+                        let reason = e.to_string();
+                        offset = reader.position().byte();
+                        if read_error.lock().unwrap().is_none() {
+                            transform.malformed.report(records, offset, &reason)?;
+                        }
+                        break;
                     }
-                } else {
-                    eprintln!("Missing recored id in file: {:?}", file_path);
+                };
+                offset = seqrec.position().byte();
+                let Ok(id) = std::str::from_utf8(seqrec.id()) else {
+                    transform.malformed.report(records, offset, "header is not valid UTF-8")?;
+                    continue;
+                };
+                let Some((first_record_id, description)) = split_header(id) else {
+                    transform.malformed.report(records, offset, "header without a record ID")?;
+                    continue;
+                };
+                let record_id = transform.renamer.rename(first_record_id);
+                let name_kept = transform.filter.keep_name(record_id);
+                // Without a line width the input wrapping is kept as is.
+                let mut seq = if line_width.is_some() { seqrec.seq() } else { seqrec.raw_seq().into() };
+                let mut length = seqrec.num_bases() as u64;
+                if name_kept && transform.alphabet.is_active() {
+                    let mut normalized = Vec::with_capacity(seq.len());
+                    transform.alphabet.normalize_lines(&seq, &mut normalized).map_err(|base| invalid_base(file_path, records, base))?;
+                    length = normalized.iter().filter(|&&b| b != b'\n' && b != b'\r').count() as u64;
+                    seq = normalized.into();
+                }
+                if !transform.filter.record(name_kept, length) {
+                    continue;
                 }
-                
+                let name = transform.name(record_id);
+                if transform.masker.is_active() {
+                    transform.masker.apply(seq.to_mut());
+                }
+                let mut fasta = vec![];
+                write_header(&mut fasta, &name, description, transform.options.keep_description)?;
+                let mut wrapper = LineWrapper::new(line_width.unwrap_or(0));
+                wrapper.write(&mut fasta, &seq)?;
+                wrapper.end_record(&mut fasta)?;
+                let record = MergeRecord {
+                    fasta,
+                    name,
+                    description: description.to_string(),
+                    mask: transform.masker.finish_record(),
+                };
+                pending = Some(record);
+            }
+        }
+        // An empty genome, as with separate output.
+        Err(e) if e.kind == ParseErrorKind::EmptyFile => {}
+        Err(e) => {
+            if read_error.lock().unwrap().is_none() {
+                transform.malformed.report(1, 0, &e.to_string())?;
+            }
+        }
+    }
+    let read_error = read_error.lock().unwrap().take();
+    if let Some(e) = read_error {
+        transform.malformed.report(records.max(1), offset, &e.to_string())?;
+    } else if let Some(record) = pending
+        && sender.send(Ok(record)).is_err()
+    {
+        return Ok(());
+    }
+    transform.report();
+    Ok(())
+}
+
+enum MergeWriter {
+    Plain(BufWriter<File>),
+    Bgzf(BgzfWriter<File>),
+    // The pool is kept next to the writer so it outlives every queued block.
+    #[cfg(feature = "c_ffi")]
+    Htslib(BGZFWriter, ThreadPool),
+}
+
+impl Write for MergeWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            MergeWriter::Plain(writer) => writer.write(buf),
+            MergeWriter::Bgzf(writer) => writer.write(buf),
+            #[cfg(feature = "c_ffi")]
+            MergeWriter::Htslib(writer, _) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            MergeWriter::Plain(writer) => writer.flush(),
+            MergeWriter::Bgzf(writer) => writer.flush(),
+            #[cfg(feature = "c_ffi")]
+            MergeWriter::Htslib(writer, _) => writer.flush(),
+        }
+    }
+}
+
+impl MergeWriter {
+    // Returns the `.gzi` block offsets for natively written BGZF output.
+    fn finish(self) -> std::io::Result<Option<Vec<(u64, u64)>>> {
+        match self {
+            MergeWriter::Plain(mut writer) => {
+                writer.flush()?;
+                Ok(None)
+            }
+            MergeWriter::Bgzf(mut writer) => {
+                writer.finish()?;
+                Ok(Some(writer.gzi_index().to_vec()))
+            }
+            #[cfg(feature = "c_ffi")]
+            MergeWriter::Htslib(writer, tpool) => {
+                drop(writer);
+                drop(tpool);
+                Ok(None)
             }
         }
     }
-    Ok(results)
 }
 
 #[allow(unused_variables)]
-fn create_all_fasta_and_merge_writer(output_file_path: &Path, bgzip_output: bool, compression_level: Option<u32>, threads: usize) -> std::io::Result<Box<dyn Write>> {
+fn create_all_fasta_and_merge_writer(output_file_path: &Path, bgzip_output: bool, compression_level: Option<u32>, threads: usize, faidx: bool) -> std::io::Result<MergeWriter> {
     if bgzip_output {
+        // The native writer is needed for the index, since it knows the
+        // offset of every BGZF block it writes.
         #[cfg(feature = "c_ffi")]
-        {
+        if !faidx {
             let compression = compression_level
                 .map(|l| CompressionLevel::Level(l as i8))
                 .unwrap_or(CompressionLevel::Default);
-            let mut writer = BGZFWriter::from_path_with_level(output_file_path, CompressionLevel::Default)
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+            let mut writer = BGZFWriter::from_path_with_level(output_file_path, compression)
+                .map_err(std::io::Error::other)?;
             let tpool = ThreadPool::new(threads as u32)
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+                .map_err(std::io::Error::other)?;
             writer
                 .set_thread_pool(&tpool)
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-            Ok(Box::new(writer))
-        }
-
-        #[cfg(not(feature = "c_ffi"))]
-        {
-            eprintln!("Error: `--merge_bgzip_output` requires feature `c_ffi` to be enabled.");
-            std::process::exit(1);
+                .map_err(std::io::Error::other)?;
+            return Ok(MergeWriter::Htslib(writer, tpool));
         }
 
+        let output_file = File::create(output_file_path)?;
+        Ok(MergeWriter::Bgzf(BgzfWriter::new(output_file, compression_level, threads)))
     } else {
         let output_file = File::create(output_file_path)?;
-        Ok(Box::new(BufWriter::new(output_file)))
+        Ok(MergeWriter::Plain(BufWriter::new(output_file)))
+    }
+}
+
+fn process_all_fasta_and_merge(args: &FastixeArgs, genomes: &[InputGenome], output_file_path: &Path, options: &TransformOptions) -> crate::Result<()> {
+    let writer = create_all_fasta_and_merge_writer(output_file_path, args.merge_bgzip_output, args.compression_level, args.threads, args.faidx)?;
+    let result = write_merge(args, genomes, output_file_path, writer, options);
+    if result.is_err() {
+        // Do not leave a truncated merge behind: a BGZF one would even end
+        // with a valid EOF block.
+        let _ = std::fs::remove_file(output_file_path);
+        for extension in ["descriptions.tsv", "mask.bed", "fai", "gzi"] {
+            let _ = std::fs::remove_file(index_path(output_file_path, extension));
+        }
     }
+    result
 }
 
-fn process_all_fasta_and_merge(args: &FastixeArgs, files: &[String], output_file_path: &Path) -> std::io::Result<()> {
-    let (sender, receiver) = unbounded();
-    files.par_iter().for_each_with(sender.clone(), |s, file_path| {
-        if let Ok(results) = process_fasta_needle(file_path.as_ref(), &args.reg, args.uppercase) {
-            for line in results {
-                s.send(line).expect("Failed to send!");
+fn write_merge(args: &FastixeArgs, genomes: &[InputGenome], output_file_path: &Path, mut writer: MergeWriter, options: &TransformOptions) -> crate::Result<()> {
+    // The index is built from the bytes as they are written, so the merged
+    // file never has to be read back.
+    let mut fai_builder = args.faidx.then(FaiBuilder::new);
+    let mut description_table = if args.description_table {
+        Some(BufWriter::new(File::create(index_path(output_file_path, "descriptions.tsv"))?))
+    } else {
+        None
+    };
+    let mut mask_bed = if options.mask_mode == MaskMode::Bed {
+        Some(BufWriter::new(File::create(index_path(output_file_path, "mask.bed"))?))
+    } else {
+        None
+    };
+
+    // Each genome gets its own bounded record channel. Genomes are handed to
+    // the workers in input order and the writer drains their channels in the
+    // same order, so at most `threads` genomes are in flight, each holding
+    // at most `channel_capacity` records. The workers are plain threads
+    // rather than rayon tasks, because the BGZF writer compresses on the
+    // rayon pool while they are blocked.
+    let (job_sender, job_receiver) = bounded::<(&InputGenome, Sender<crate::Result<MergeRecord>>)>(0);
+    let (genome_sender, genome_receiver) = bounded::<(usize, Receiver<crate::Result<MergeRecord>>)>(args.threads);
+    let mut duplicates = DuplicateTracker::default();
+    std::thread::scope(|scope| -> crate::Result<()> {
+        scope.spawn(move || {
+            for (index, genome) in genomes.iter().enumerate() {
+                let (record_sender, record_receiver) = bounded(args.channel_capacity);
+                // Both sends only fail once the writer has given up.
+                if genome_sender.send((index, record_receiver)).is_err() || job_sender.send((genome, record_sender)).is_err() {
+                    break;
+                }
+            }
+        });
+        for _ in 0..args.threads.max(1) {
+            let job_receiver = job_receiver.clone();
+            scope.spawn(move || {
+                for (genome, record_sender) in job_receiver {
+                    let result = genome_prefix(args, genome).and_then(|prefix| {
+                        process_fasta_needle(options.genome(genome, prefix), &record_sender)
+                    });
+                    // The writer returns the error once it gets to this
+                    // genome, which stops everything else.
+                    if let Err(e) = result {
+                        let _ = record_sender.send(Err(e));
+                    }
+                }
+            });
+        }
+        drop(job_receiver);
+
+        for (index, record_receiver) in genome_receiver {
+            for record in record_receiver {
+                let mut record = record?;
+                // Once a duplicate is found with `fail` nothing more is
+                // written, but the rest is still read to report them all.
+                if !duplicates.check(&mut record, index, args.on_duplicate) || !duplicates.duplicates.is_empty() {
+                    continue;
+                }
+                writer.write_all(&record.fasta)?;
+                if let Some(fai_builder) = fai_builder.as_mut() {
+                    fai_builder.update(&record.fasta)?;
+                }
+                if let Some(table) = description_table.as_mut() {
+                    writeln!(table, "{}\t{}", record.name, record.description)?;
+                }
+                if let Some(bed) = mask_bed.as_mut() {
+                    write_bed(bed, &record.name, &record.mask)?;
+                }
             }
         }
+        Ok(())
+    })?;
 
-    });
-    drop(sender);
+    if !duplicates.duplicates.is_empty() {
+        for (name, first, duplicate) in &duplicates.duplicates {
+            error!("Duplicate sequence name '{}' in {} (first seen in {})", name, genomes[*duplicate].path, genomes[*first].path);
+        }
+        return Err(Error::Naming(
+            format!("{} duplicate sequence names in the merge output, see --on-duplicate", duplicates.duplicates.len()),
+        ));
+    }
+
+    if let Some(mut table) = description_table {
+        table.flush()?;
+    }
+    if let Some(mut mask_bed) = mask_bed {
+        mask_bed.flush()?;
+    }
 
-    let mut writer = create_all_fasta_and_merge_writer(output_file_path, args.merge_bgzip_output, args.compression_level, args.threads)?;
+    let gzi_index = writer.finish()?;
 
-    for line in receiver {
-        writer.write_all(line.as_bytes())?;
+    if let Some(fai_builder) = fai_builder {
+        write_fai(&index_path(output_file_path, "fai"), &fai_builder.finish()?)?;
+        if let Some(gzi_index) = gzi_index {
+            write_gzi(&index_path(output_file_path, "gzi"), &gzi_index)?;
+        }
     }
 
     Ok(())
-} 
-
-fn process_all_fasta(args: &FastixeArgs, input_genomes: &[String]) -> std::io::Result<()> {
-    if input_genomes.len() > 1 {
-        input_genomes.par_iter().try_for_each(|input_genome| {
-            let output_genome = make_output_path(input_genome.as_ref(), &args.out_directory, args.gzip_output);
-            let prefix = extract_prefix_from_path(input_genome.as_ref(), args.reg.as_ref())?;
-            process_fasta(input_genome.as_ref(), &output_genome, &prefix, args.uppercase, args.gzip_output, args.compression_level, args.is_stdout)
-        })?;
-    } else {
-        let input_genomes_first = input_genomes.first().unwrap().as_ref();
-        let output_genome = if input_genomes_first == Path::new("-") {
-            PathBuf::from("-") 
-        } else {
-            make_output_path(input_genomes_first, &args.out_directory, args.gzip_output)
-        };
-        if args.prefix.is_some() {
-            process_fasta(input_genomes_first, &output_genome, &args.prefix.as_ref().unwrap(), args.uppercase, args.gzip_output, args.compression_level, args.is_stdout)?;
-        } else {
-            let prefix = extract_prefix_from_path(input_genomes_first, args.reg.as_ref())?;
-            process_fasta(input_genomes_first, &output_genome, prefix.as_str(), args.uppercase, args.gzip_output, args.compression_level, args.is_stdout)?;
+}
+
+// The full path of `path`, which does not have to exist yet.
+fn canonical_path(path: &Path) -> std::io::Result<PathBuf> {
+    if path.exists() {
+        return path.canonicalize();
+    }
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    Ok(dir.canonicalize()?.join(path.file_name().unwrap_or_default()))
+}
+
+// Fail before anything is written if two genomes would be written to the same
+// file (e.g. x.fna and x.fna.gz) or an output would overwrite an input.
+fn check_output_paths(args: &FastixeArgs, genomes: &[InputGenome]) -> crate::Result<()> {
+    let files: Vec<_> = genomes.iter().filter(|genome| genome.path != "-").collect();
+    let mut inputs = HashMap::new();
+    for genome in &files {
+        inputs.insert(canonical_path(genome.path.as_ref())?, genome.path.as_str());
+    }
+    let mut written = HashMap::new();
+    for genome in &files {
+        let output = make_output_path(genome.path.as_ref(), &args.out_directory, args.gzip_output)?;
+        let canonical = canonical_path(&output)?;
+        if let Some(input) = inputs.get(&canonical) {
+            return Err(Error::Args(format!("The output {:?} of {} would overwrite the input {}", output, genome.path, input)));
+        }
+        if let Some(other) = written.insert(canonical, genome.path.as_str()) {
+            return Err(Error::Args(format!("{} and {} would both be written to {:?}", other, genome.path, output)));
         }
     }
     Ok(())
 }
 
-pub fn fastixe(args: FastixeArgs) -> std::io::Result<()> {
-    let mut input_genomes = vec![];
-
-    check_args_valid(&args);
-    parse_files(&args, &mut input_genomes);
-    create_dir_all(&args.out_directory)?;
-    // println!("input genomes: {:?}", input_genomes);
-    if args.merge_output {
-        let mut merged_path = Path::new(&args.out_directory).join(&args.merge_output_file_path);
-        if args.merge_bgzip_output {
-            merged_path.set_extension("gz");
+fn process_all_fasta(args: &FastixeArgs, input_genomes: &[InputGenome], options: &TransformOptions) -> crate::Result<()> {
+    if !args.is_stdout {
+        check_output_paths(args, input_genomes)?;
+    }
+    let process = |input_genome: &InputGenome| {
+        let output_genome = if input_genome.path == "-" {
+            PathBuf::from("-")
+        } else {
+            make_output_path(input_genome.path.as_ref(), &args.out_directory, args.gzip_output)?
         };
-        process_all_fasta_and_merge(&args,&input_genomes, &merged_path)?;
-        if args.faidx {
-            #[cfg(feature = "c_ffi")]
-            build(&merged_path).expect("Failed to build FASTA index");
+        let prefix = genome_prefix(args, input_genome)?;
+        process_fasta(args, options.genome(input_genome, prefix), &output_genome)
+    };
+    match input_genomes {
+        [input_genome] => process(input_genome),
+        _ => input_genomes.par_iter().try_for_each(process),
+    }
+}
 
-            #[cfg(not(feature = "c_ffi"))]
-            {
-                eprintln!("Error: `--faidx` requires feature `c_ffi` to be enabled.");
-                std::process::exit(1);
-            }        
+/// How sequence names get their PanSN prefix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PrefixStrategy {
+    /// Sample and haplotype from the file name, see `--regex`.
+    Regex(String),
+    /// The same prefix, e.g. `HG002#1#`, for a single input genome.
+    Fixed(String),
+}
+
+/// Where the renamed genomes are written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Output {
+    /// One file per genome in `directory`, bgzipped if `gzip`.
+    Separate { directory: PathBuf, gzip: bool },
+    /// A single genome to stdout.
+    Stdout,
+    /// All genomes in one file; with `bgzip` its extension becomes `.gz`,
+    /// and `faidx` writes the `.fai` (and `.gzi`) index next to it.
+    Merged { path: PathBuf, bgzip: bool, faidx: bool },
+}
+
+/// Renaming and merging without the command line.
+///
+/// Nothing global is set up: log messages go to whatever `log` logger the
+/// caller installed, and the parallel work runs on the current rayon pool.
+///
+/// ```no_run
+/// use panutils::fastixe::{Fastixe, Output, PrefixStrategy};
+///
+/// Fastixe::new()
+///     .input_directory("assemblies")
+///     .prefix(PrefixStrategy::Regex(r"^[^.]+".to_string()))
+///     .output(Output::Merged { path: "pangenome.fa".into(), bgzip: true, faidx: true })
+///     .threads(8)
+///     .run()?;
+/// # Ok::<(), panutils::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct Fastixe {
+    args: FastixeArgs,
+}
+
+impl Default for Fastixe {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<FastixeArgs> for Fastixe {
+    fn from(args: FastixeArgs) -> Self {
+        Self { args }
+    }
+}
+
+impl Fastixe {
+    /// The same defaults as the command line.
+    pub fn new() -> Self {
+        Self {
+            args: FastixeArgs {
+                out_directory: PathBuf::from("genomes"),
+                reg: String::from("[^_]+_[^_]+"),
+                delimiter: '#',
+                merge_output_file_path: String::from("merged.fa"),
+                channel_capacity: 4,
+                threads: 1,
+                ..FastixeArgs::default()
+            },
         }
-    } else {
-        process_all_fasta(&args, &input_genomes)?;
     }
 
-    Ok(())
+    pub fn input_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.args.input_files.get_or_insert_with(Vec::new).push(path.into());
+        self
+    }
+
+    pub fn input_files<P: Into<PathBuf>>(mut self, paths: impl IntoIterator<Item = P>) -> Self {
+        self.args.input_files.get_or_insert_with(Vec::new).extend(paths.into_iter().map(Into::into));
+        self
+    }
+
+    /// A file with one genome path per line.
+    pub fn input_list(mut self, path: impl Into<PathBuf>) -> Self {
+        self.args.input_list = Some(path.into());
+        self
+    }
+
+    /// Every FASTA file in `path`.
+    pub fn input_directory(mut self, path: impl Into<PathBuf>) -> Self {
+        self.args.input_directory = Some(path.into());
+        self
+    }
+
+    /// A sample sheet; its genomes take their sample, haplotype and contig
+    /// names from it rather than from the prefix strategy.
+    pub fn sample_sheet(mut self, path: impl Into<PathBuf>) -> Self {
+        self.args.sample_sheet = Some(path.into());
+        self
+    }
+
+    /// Read a single genome from stdin; needs `PrefixStrategy::Fixed`.
+    pub fn stdin(mut self) -> Self {
+        self.args.input_stdin = Some("-".to_string());
+        self
+    }
+
+    /// Skip malformed records and summarise them per file instead of
+    /// returning an error for the first one.
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.args.lenient = lenient;
+        self
+    }
+
+    pub fn prefix(mut self, prefix: PrefixStrategy) -> Self {
+        match prefix {
+            PrefixStrategy::Regex(regex) => {
+                self.args.reg = regex;
+                self.args.prefix = None;
+            }
+            PrefixStrategy::Fixed(prefix) => self.args.prefix = Some(prefix),
+        }
+        self
+    }
+
+    pub fn delimiter(mut self, delimiter: char) -> Self {
+        self.args.delimiter = delimiter;
+        self
+    }
+
+    /// Rewrite names that already are PanSN names with `from` to use the
+    /// delimiter instead of adding a prefix.
+    pub fn convert_delimiter(mut self, from: char) -> Self {
+        self.args.convert_delimiter = Some(from);
+        self
+    }
+
+    pub fn rename_map(mut self, path: impl Into<PathBuf>) -> Self {
+        self.args.rename_map = Some(path.into());
+        self
+    }
+
+    pub fn min_length(mut self, length: u64) -> Self {
+        self.args.min_length = Some(length);
+        self
+    }
+
+    pub fn max_length(mut self, length: u64) -> Self {
+        self.args.max_length = Some(length);
+        self
+    }
+
+    pub fn include_regex(mut self, regex: impl Into<String>) -> Self {
+        self.args.include_regex = Some(regex.into());
+        self
+    }
+
+    pub fn exclude_regex(mut self, regex: impl Into<String>) -> Self {
+        self.args.exclude_regex = Some(regex.into());
+        self
+    }
+
+    pub fn uppercase(mut self, uppercase: bool) -> Self {
+        self.args.uppercase = uppercase;
+        self
+    }
+
+    pub fn mask_mode(mut self, mode: MaskMode) -> Self {
+        self.args.mask_mode = mode;
+        self
+    }
+
+    pub fn alphabet(mut self, mode: AlphabetMode) -> Self {
+        self.args.alphabet = mode;
+        self
+    }
+
+    /// Wrap sequences at `width` bases per line (0 = unwrapped).
+    pub fn line_width(mut self, width: usize) -> Self {
+        self.args.line_width = Some(width);
+        self
+    }
+
+    pub fn keep_description(mut self, keep: bool) -> Self {
+        self.args.keep_description = keep;
+        self
+    }
+
+    pub fn description_table(mut self, write: bool) -> Self {
+        self.args.description_table = write;
+        self
+    }
+
+    pub fn output(mut self, output: Output) -> Self {
+        self.args.is_stdout = false;
+        self.args.merge_output = false;
+        match output {
+            Output::Separate { directory, gzip } => {
+                self.args.out_directory = directory;
+                self.args.gzip_output = gzip;
+            }
+            Output::Stdout => self.args.is_stdout = true,
+            Output::Merged { path, bgzip, faidx } => {
+                self.args.merge_output = true;
+                self.args.merge_bgzip_output = bgzip;
+                self.args.faidx = faidx;
+                self.args.out_directory = path.parent().map(Path::to_path_buf).unwrap_or_default();
+                self.args.merge_output_file_path = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+            }
+        }
+        self
+    }
+
+    pub fn sort(mut self, order: MergeOrder) -> Self {
+        self.args.sort = order;
+        self
+    }
+
+    pub fn on_duplicate(mut self, policy: DuplicatePolicy) -> Self {
+        self.args.on_duplicate = policy;
+        self
+    }
+
+    /// Records buffered per genome while merging.
+    pub fn channel_capacity(mut self, capacity: usize) -> Self {
+        self.args.channel_capacity = capacity;
+        self
+    }
+
+    /// Compression level (0-9) of gzip and BGZF output.
+    pub fn compression_level(mut self, level: u32) -> Self {
+        self.args.compression_level = Some(level);
+        self
+    }
+
+    /// Genomes parsed at once while merging and BGZF blocks compressed at
+    /// once.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.args.threads = threads;
+        self
+    }
+
+    pub fn run(&self) -> crate::Result<()> {
+        let args = &self.args;
+        let mut input_genomes = vec![];
+
+        check_args_valid(args)?;
+        parse_files(args, &mut input_genomes)?;
+        create_dir_all(&args.out_directory)?;
+        let rename_map = args.rename_map.as_deref().map(load_rename_map).transpose()?;
+        let filter = ContigFilter::new(args.min_length, args.max_length, args.include_regex.as_deref(), args.exclude_regex.as_deref())?;
+        let options = TransformOptions::new(args, rename_map.as_ref(), &filter);
+        // println!("input genomes: {:?}", input_genomes);
+        if args.merge_output {
+            let mut merged_path = Path::new(&args.out_directory).join(&args.merge_output_file_path);
+            if args.merge_bgzip_output {
+                merged_path.set_extension("gz");
+            };
+            process_all_fasta_and_merge(args, &input_genomes, &merged_path, &options)?;
+        } else {
+            process_all_fasta(args, &input_genomes, &options)?;
+        }
+
+        Ok(())
+    }
+}
+
+pub fn fastixe(args: FastixeArgs) -> crate::Result<()> {
+    Fastixe::from(args).run()
 }
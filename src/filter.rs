@@ -0,0 +1,69 @@
+
+use regex::Regex;
+
+/// What a `ContigFilter` let through and dropped for one genome.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FilterCounts {
+    pub kept: usize,
+    pub dropped_by_name: usize,
+    pub dropped_by_length: usize,
+    pub dropped_bases: u64,
+}
+
+/// Length and contig ID filters. Clone one per genome, so every genome gets
+/// its own counts.
+#[derive(Debug, Default, Clone)]
+pub struct ContigFilter {
+    min_length: Option<u64>,
+    max_length: Option<u64>,
+    include: Option<Regex>,
+    exclude: Option<Regex>,
+    counts: FilterCounts,
+}
+
+impl ContigFilter {
+    pub fn new(min_length: Option<u64>, max_length: Option<u64>, include: Option<&str>, exclude: Option<&str>) -> crate::Result<Self> {
+        Ok(Self {
+            min_length,
+            max_length,
+            include: include.map(Regex::new).transpose()?,
+            exclude: exclude.map(Regex::new).transpose()?,
+            counts: FilterCounts::default(),
+        })
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.has_length_bounds() || self.include.is_some() || self.exclude.is_some()
+    }
+
+    /// Whether records have to be complete before it can be decided if
+    /// they are kept.
+    pub fn has_length_bounds(&self) -> bool {
+        self.min_length.is_some() || self.max_length.is_some()
+    }
+
+    /// Whether a contig ID passes `--include-regex` and `--exclude-regex`.
+    pub fn keep_name(&self, record_id: &str) -> bool {
+        self.include.as_ref().is_none_or(|re| re.is_match(record_id))
+            && !self.exclude.as_ref().is_some_and(|re| re.is_match(record_id))
+    }
+
+    /// Count a complete record and return whether it is kept: its ID
+    /// passed `keep_name` and its length is within the bounds.
+    pub fn record(&mut self, name_kept: bool, length: u64) -> bool {
+        if !name_kept {
+            self.counts.dropped_by_name += 1;
+        } else if self.min_length.is_some_and(|min| length < min) || self.max_length.is_some_and(|max| length > max) {
+            self.counts.dropped_by_length += 1;
+        } else {
+            self.counts.kept += 1;
+            return true;
+        }
+        self.counts.dropped_bases += length;
+        false
+    }
+
+    pub fn counts(&self) -> FilterCounts {
+        self.counts
+    }
+}
@@ -1,3 +1,18 @@
 
+pub mod alphabet;
 pub mod cmdline;
+pub mod error;
+pub mod bgzf;
+pub mod faidx;
 pub mod fastixe;
+pub mod filter;
+pub mod mask;
+pub mod pansn;
+pub mod pansn_check;
+pub mod reader;
+pub mod rename;
+pub mod sample_sheet;
+pub mod stats;
+pub mod unfastixe;
+
+pub use error::{Error, Result};
@@ -1,12 +1,67 @@
 
 use panutils::cmdline::*;
+use panutils::faidx;
 use panutils::fastixe;
+use panutils::pansn_check;
+use panutils::stats;
+use panutils::unfastixe;
+use panutils::Error;
 use clap::Parser;
+use log::LevelFilter;
+use std::process::ExitCode;
 
-fn main() -> std::io::Result<()> {
-    let cli = Cli::parse();
-    match cli.mode {
-        Mode::Fastixe(fastixe_args) => fastixe::fastixe(fastixe_args),
+// Usage errors exit with 2, like clap's own.
+fn exit_code(error: &Error) -> u8 {
+    match error {
+        Error::Io(_) => 1,
+        Error::Args(_) | Error::Regex(_) => 2,
+        Error::Naming(_) => 3,
+        Error::Format(_) => 4,
     }
+}
 
+// The library leaves the logger and the rayon pool to its caller.
+fn init_logger(trace: bool, debug: bool) {
+    let level = if trace {
+        LevelFilter::Trace
+    } else if debug {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    };
+    simple_logger::SimpleLogger::new().with_level(level).init().unwrap();
+}
+
+fn init_thread_pool(threads: usize) {
+    rayon::ThreadPoolBuilder::new().num_threads(threads).build_global().unwrap();
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = match cli.mode {
+        Mode::Fastixe(fastixe_args) => {
+            init_logger(fastixe_args.trace, fastixe_args.debug);
+            init_thread_pool(fastixe_args.threads);
+            fastixe::fastixe(fastixe_args)
+        }
+        Mode::PansnCheck(pansn_check_args) => {
+            init_logger(false, false);
+            pansn_check::pansn_check(pansn_check_args)
+        }
+        Mode::Unfastixe(unfastixe_args) => {
+            init_logger(false, false);
+            init_thread_pool(unfastixe_args.threads);
+            unfastixe::unfastixe(unfastixe_args)
+        }
+        Mode::Stats(stats_args) => stats::stats(stats_args),
+        Mode::Faidx(faidx_args) => faidx::faidx(faidx_args),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::from(exit_code(&e))
+        }
+    }
 }
@@ -0,0 +1,82 @@
+
+use std::io::Write;
+
+use crate::cmdline::MaskMode;
+
+/// Handles soft-masked (lowercase) bases one record at a time: keeps them,
+/// uppercases them, hard-masks them to N, or uppercases them after noting
+/// the masked intervals for a BED file.
+#[derive(Debug, Default)]
+pub struct SoftMasker {
+    mode: MaskMode,
+    // Bases of the current record seen so far and the start of the open
+    // masked interval.
+    position: u64,
+    start: Option<u64>,
+    intervals: Vec<(u64, u64)>,
+}
+
+impl SoftMasker {
+    pub fn new(mode: MaskMode) -> Self {
+        Self { mode, ..Self::default() }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.mode != MaskMode::Keep
+    }
+
+    pub fn writes_bed(&self) -> bool {
+        self.mode == MaskMode::Bed
+    }
+
+    /// Apply the mode to the next piece of the current record. Line endings
+    /// are skipped, so wrapped sequence can be passed as is.
+    pub fn apply(&mut self, seq: &mut [u8]) {
+        if !self.is_active() {
+            return;
+        }
+        for base in seq {
+            if matches!(*base, b'\n' | b'\r') {
+                continue;
+            }
+            match self.mode {
+                MaskMode::Keep => {}
+                MaskMode::Upper => base.make_ascii_uppercase(),
+                MaskMode::Hard => {
+                    if base.is_ascii_lowercase() {
+                        *base = b'N';
+                    }
+                }
+                MaskMode::Bed => {
+                    match (base.is_ascii_lowercase(), self.start) {
+                        (true, None) => self.start = Some(self.position),
+                        (false, Some(start)) => {
+                            self.intervals.push((start, self.position));
+                            self.start = None;
+                        }
+                        _ => {}
+                    }
+                    base.make_ascii_uppercase();
+                }
+            }
+            self.position += 1;
+        }
+    }
+
+    /// End the current record and return its masked intervals, 0-based and
+    /// half-open like BED.
+    pub fn finish_record(&mut self) -> Vec<(u64, u64)> {
+        if let Some(start) = self.start.take() {
+            self.intervals.push((start, self.position));
+        }
+        self.position = 0;
+        std::mem::take(&mut self.intervals)
+    }
+}
+
+pub fn write_bed(writer: &mut dyn Write, name: &str, intervals: &[(u64, u64)]) -> std::io::Result<()> {
+    for (start, end) in intervals {
+        writeln!(writer, "{}\t{}\t{}", name, start, end)?;
+    }
+    Ok(())
+}
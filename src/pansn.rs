@@ -0,0 +1,65 @@
+
+use std::sync::LazyLock;
+use regex::Regex;
+
+// hap1 / haplotype2 / hap_1, pat / paternal (1) and mat / maternal (2),
+// delimited by '.', '_' or '-' as in hifiasm, verkko and HPRC file names.
+static HAPLOTYPE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)(?:^|[._-])(?:hap(?:lotype)?[_-]?(?P<hap>\d+)|(?P<pat>pat(?:ernal)?)|(?P<mat>mat(?:ernal)?))(?:[._-]|$)").unwrap()
+});
+
+/// Recognise the haplotype from common phased assembly naming, e.g.
+/// `HG002.hap1.fa` -> 1, `HG002.paternal.fa` -> 1, `HG002.mat.fa` -> 2.
+pub fn haplotype_from_name(name: &str) -> Option<u32> {
+    let caps = HAPLOTYPE_REGEX.captures(name)?;
+    if let Some(hap) = caps.name("hap") {
+        hap.as_str().parse().ok()
+    } else if caps.name("pat").is_some() {
+        Some(1)
+    } else {
+        Some(2)
+    }
+}
+
+/// Parse the value of a `hap` capture group: a plain integer or any of the
+/// names `haplotype_from_name` understands.
+pub fn parse_haplotype(hap: &str) -> Option<u32> {
+    hap.parse().ok().or_else(|| haplotype_from_name(hap))
+}
+
+/// A sequence name split according to PanSN: `sample#haplotype#contig`.
+/// The contig is everything after the second delimiter, so it may contain
+/// the delimiter itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PanSN<'a> {
+    pub sample: &'a str,
+    pub haplotype: &'a str,
+    pub contig: &'a str,
+}
+
+impl PanSN<'_> {
+    /// The haplotype as an integer, if it is one.
+    pub fn haplotype_id(&self) -> Option<u32> {
+        self.haplotype.parse().ok()
+    }
+
+    /// The name with `delimiter` between the fields.
+    pub fn join(&self, delimiter: char) -> String {
+        format!("{}{}", pansn_prefix(self.sample, self.haplotype, delimiter), self.contig)
+    }
+}
+
+/// `sample#haplotype#`, the prefix fastixe puts in front of contig names.
+pub fn pansn_prefix(sample: &str, haplotype: &str, delimiter: char) -> String {
+    format!("{0}{2}{1}{2}", sample, haplotype, delimiter)
+}
+
+/// Split `name` into its PanSN fields; `None` unless all three are non-empty.
+pub fn parse_pansn(name: &str, delimiter: char) -> Option<PanSN<'_>> {
+    let mut fields = name.splitn(3, delimiter);
+    let (sample, haplotype, contig) = (fields.next()?, fields.next()?, fields.next()?);
+    if sample.is_empty() || haplotype.is_empty() || contig.is_empty() {
+        return None;
+    }
+    Some(PanSN { sample, haplotype, contig })
+}
@@ -0,0 +1,131 @@
+
+use std::path::Path;
+use std::io::BufRead;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use crate::cmdline::*;
+use crate::error::Error;
+use crate::pansn::parse_pansn;
+use crate::reader::{open_reader, strip_compressed_extension};
+use log::*;
+
+fn detect_format(path: &Path, first_line: &[u8]) -> NameFormat {
+    let file_name = path.file_name().map(|f| f.to_string_lossy().to_string()).unwrap_or_default();
+    let file_name = strip_compressed_extension(&file_name);
+    if file_name.ends_with(".fai") {
+        NameFormat::Fai
+    } else if file_name.ends_with(".gfa") {
+        NameFormat::Gfa
+    } else if file_name.ends_with(".paf") {
+        NameFormat::Paf
+    } else if first_line.starts_with(b"H\t") || first_line.starts_with(b"S\t") {
+        NameFormat::Gfa
+    } else {
+        NameFormat::Fasta
+    }
+}
+
+// Collect every sequence name in the file, in order. PAF names are only
+// reported once, since every alignment repeats them.
+fn read_names(path: &Path, format: NameFormat, delimiter: char) -> std::io::Result<Vec<String>> {
+    let mut reader = open_reader(path)?;
+    let format = match format {
+        NameFormat::Auto => detect_format(path, reader.fill_buf()?),
+        format => format,
+    };
+
+    let mut names = vec![];
+    let mut seen = HashSet::new();
+    let mut line = vec![];
+    while reader.read_until(b'\n', &mut line)? > 0 {
+        let text = String::from_utf8_lossy(&line);
+        let text = text.trim_end_matches(['\n', '\r']);
+        let mut fields = text.split('\t');
+        match format {
+            NameFormat::Fasta => {
+                if let Some(header) = text.strip_prefix('>') {
+                    names.push(header.split_whitespace().next().unwrap_or("").to_string());
+                }
+            }
+            NameFormat::Fai => {
+                if let Some(name) = fields.next().filter(|n| !n.is_empty()) {
+                    names.push(name.to_string());
+                }
+            }
+            NameFormat::Gfa => match fields.next() {
+                Some("P") => names.extend(fields.next().map(String::from)),
+                // W lines carry the PanSN fields in separate columns. A
+                // contig can be split across several walks, so the range is
+                // part of the name, as vg writes it: contig[start-end].
+                Some("W") => {
+                    let walk: Vec<&str> = fields.take(5).collect();
+                    if let [sample, haplotype, contig, ..] = walk[..] {
+                        let range = match walk[3..] {
+                            [start, end] if start != "*" && end != "*" => format!("[{start}-{end}]"),
+                            _ => String::new(),
+                        };
+                        names.push(format!("{sample}{delimiter}{haplotype}{delimiter}{contig}{range}"));
+                    }
+                }
+                _ => {}
+            },
+            NameFormat::Paf => {
+                let fields: Vec<&str> = fields.collect();
+                for name in [fields.first(), fields.get(5)].into_iter().flatten() {
+                    if seen.insert(name.to_string()) {
+                        names.push(name.to_string());
+                    }
+                }
+            }
+            NameFormat::Auto => unreachable!(),
+        }
+        line.clear();
+    }
+    Ok(names)
+}
+
+pub fn pansn_check(args: PansnCheckArgs) -> crate::Result<()> {
+    let mut problems = 0;
+    let mut report = |file: &Path, kind: &str, name: &str, detail: String| {
+        problems += 1;
+        println!("{}\t{}\t{}\t{}", file.display(), kind, name, detail);
+    };
+
+    for file in &args.input_files {
+        let names = read_names(file, args.format, args.delimiter)?;
+
+        let mut seen = HashSet::new();
+        let mut haplotypes: BTreeMap<&str, BTreeSet<u32>> = BTreeMap::new();
+        for name in &names {
+            if !seen.insert(name.as_str()) {
+                report(file, "duplicate", name, "name occurs more than once".to_string());
+            }
+            let Some(pansn) = parse_pansn(name, args.delimiter) else {
+                report(file, "malformed", name, format!("expected sample{0}haplotype{0}contig", args.delimiter));
+                continue;
+            };
+            match pansn.haplotype_id() {
+                Some(hap) => {
+                    haplotypes.entry(pansn.sample).or_default().insert(hap);
+                }
+                None => report(file, "haplotype", name, format!("haplotype '{}' is not an integer", pansn.haplotype)),
+            }
+        }
+
+        // Haplotypes of a sample should be numbered 0.. or 1.. without gaps.
+        for (sample, haps) in haplotypes {
+            let first = *haps.first().unwrap();
+            let last = *haps.last().unwrap();
+            if first > 1 || (last - first + 1) as usize != haps.len() {
+                let haps: Vec<String> = haps.iter().map(u32::to_string).collect();
+                report(file, "haplotype-gap", sample, format!("haplotypes {} are not numbered consecutively from 0 or 1", haps.join(",")));
+            }
+        }
+        info!("{}: checked {} names", file.display(), names.len());
+    }
+
+    if problems > 0 {
+        return Err(Error::Naming(format!("{} PanSN problems found", problems)));
+    }
+    Ok(())
+}
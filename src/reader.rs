@@ -0,0 +1,60 @@
+
+use std::path::Path;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, stdin};
+
+/// File name suffixes of the compressed inputs `open_reader` can decode.
+pub const COMPRESSED_EXTENSIONS: [&str; 5] = [".gz", ".bgz", ".zst", ".bz2", ".xz"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Bzip2,
+    Xz,
+}
+
+/// Detect the compression from the magic bytes at the start of `buf`.
+/// BGZF is reported as gzip.
+pub fn sniff_compression(buf: &[u8]) -> Compression {
+    if buf.starts_with(&[0x1f, 0x8b]) {
+        Compression::Gzip
+    } else if buf.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Compression::Zstd
+    } else if buf.starts_with(b"BZh") {
+        Compression::Bzip2
+    } else if buf.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+        Compression::Xz
+    } else {
+        Compression::None
+    }
+}
+
+/// Strip a known compression suffix, e.g. `a.fna.zst` -> `a.fna`.
+pub fn strip_compressed_extension(file_name: &str) -> &str {
+    COMPRESSED_EXTENSIONS
+        .iter()
+        .find_map(|ext| file_name.strip_suffix(ext))
+        .unwrap_or(file_name)
+}
+
+/// Open a file (or `-` for stdin) and transparently decode gzip/BGZF, zstd,
+/// bzip2 or xz, detected from the content rather than the file name.
+pub fn open_reader(file_path: &Path) -> std::io::Result<Box<dyn BufRead + Send>> {
+    let input: Box<dyn Read + Send> = if file_path == Path::new("-") {
+        Box::new(stdin())
+    } else {
+        Box::new(File::open(file_path)?)
+    };
+    let mut reader = BufReader::new(input);
+
+    let reader: Box<dyn BufRead + Send> = match sniff_compression(reader.fill_buf()?) {
+        Compression::None => Box::new(reader),
+        Compression::Gzip => Box::new(BufReader::new(flate2::bufread::MultiGzDecoder::new(reader))),
+        Compression::Zstd => Box::new(BufReader::new(zstd::stream::read::Decoder::with_buffer(reader)?)),
+        Compression::Bzip2 => Box::new(BufReader::new(bzip2::bufread::MultiBzDecoder::new(reader))),
+        Compression::Xz => Box::new(BufReader::new(liblzma::bufread::XzDecoder::new_multi_decoder(reader))),
+    };
+    Ok(reader)
+}
@@ -0,0 +1,109 @@
+
+use std::path::Path;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::collections::HashMap;
+
+use crate::error::Error;
+
+/// Load a two-column contig rename table (old ID, new ID), separated by a
+/// tab or other whitespace. Blank lines and lines starting with '#' are
+/// skipped. An NCBI `*_assembly_report.txt` is recognised by its
+/// `# Assembly name:` line and read with `parse_assembly_report`.
+pub fn load_rename_map(path: &Path) -> crate::Result<HashMap<String, String>> {
+    let lines: Vec<String> = BufReader::new(File::open(path)?).lines().collect::<Result<_, _>>()?;
+    if lines.iter().any(|line| line.starts_with("# Assembly name:")) {
+        return parse_assembly_report(path, &lines);
+    }
+    let mut map = HashMap::new();
+    for (line_number, line) in lines.into_iter().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        match (fields.next(), fields.next(), fields.next()) {
+            (Some(old), Some(new), None) => {
+                map.insert(old.to_string(), new.to_string());
+            }
+            _ => {
+                return Err(Error::Format(
+                    format!("{}:{}: expected two columns (old ID, new ID)", path.display(), line_number + 1),
+                ));
+            }
+        }
+    }
+    Ok(map)
+}
+
+/// Map the GenBank and RefSeq accessions and the sequence name of every
+/// sequence in an NCBI assembly report to its UCSC-style name (e.g.
+/// `NC_000001.11` -> `chr1`), or to the sequence name where there is none.
+fn parse_assembly_report(path: &Path, lines: &[String]) -> crate::Result<HashMap<String, String>> {
+    let invalid = |msg: String| Error::Format(format!("{}: {}", path.display(), msg));
+
+    // The column names are on the last comment line.
+    let header = lines
+        .iter()
+        .rfind(|line| line.starts_with("# Sequence-Name"))
+        .ok_or_else(|| invalid("missing the '# Sequence-Name' header line".to_string()))?;
+    let columns: Vec<&str> = header.trim_start_matches("# ").split('\t').map(str::trim).collect();
+    let column = |name: &str| {
+        columns.iter().position(|c| *c == name).ok_or_else(|| invalid(format!("missing the {} column", name)))
+    };
+    let sequence_name = column("Sequence-Name")?;
+    let genbank = column("GenBank-Accn")?;
+    let refseq = column("RefSeq-Accn")?;
+    // Older reports have no UCSC names; the Sequence-Name is used instead.
+    let ucsc = columns.iter().position(|c| *c == "UCSC-style-name");
+
+    let mut map = HashMap::new();
+    for line in lines.iter().filter(|line| !line.starts_with('#') && !line.trim().is_empty()) {
+        let fields: Vec<&str> = line.split('\t').map(str::trim).collect();
+        let field = |i: usize| fields.get(i).copied().filter(|f| !f.is_empty() && *f != "na");
+        let Some(new) = ucsc.and_then(field).or_else(|| field(sequence_name)) else {
+            continue;
+        };
+        for old in [field(genbank), field(refseq), field(sequence_name)].into_iter().flatten() {
+            if old != new {
+                map.insert(old.to_string(), new.to_string());
+            }
+        }
+    }
+    Ok(map)
+}
+
+/// Renames the contigs of one genome. Its own table (the sample sheet
+/// `rename` column) takes precedence over the global `--rename-map`; IDs
+/// found in neither are kept and remembered, so they can be reported.
+pub struct ContigRenamer<'a> {
+    genome: Option<&'a HashMap<String, String>>,
+    global: Option<&'a HashMap<String, String>>,
+    unmapped: Vec<String>,
+}
+
+impl<'a> ContigRenamer<'a> {
+    pub fn new(genome: Option<&'a HashMap<String, String>>, global: Option<&'a HashMap<String, String>>) -> Self {
+        Self { genome, global, unmapped: vec![] }
+    }
+
+    pub fn rename<'b>(&mut self, record_id: &'b str) -> &'b str
+    where
+        'a: 'b,
+    {
+        if self.genome.is_none() && self.global.is_none() {
+            return record_id;
+        }
+        match [self.genome, self.global].into_iter().flatten().find_map(|map| map.get(record_id)) {
+            Some(new) => new,
+            None => {
+                self.unmapped.push(record_id.to_string());
+                record_id
+            }
+        }
+    }
+
+    /// IDs that were kept because no table had them.
+    pub fn unmapped(&self) -> &[String] {
+        &self.unmapped
+    }
+}
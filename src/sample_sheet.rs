@@ -0,0 +1,73 @@
+
+use std::path::{Path, PathBuf};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::collections::HashMap;
+
+use crate::error::Error;
+use crate::pansn::parse_haplotype;
+use crate::rename::load_rename_map;
+
+/// One genome of a sample sheet.
+#[derive(Debug, Clone)]
+pub struct SampleSheetRecord {
+    pub path: PathBuf,
+    pub sample: String,
+    pub haplotype: u32,
+    /// Contig rename table loaded from the optional `rename` column.
+    pub contig_names: Option<HashMap<String, String>>,
+}
+
+/// Parse a TSV or CSV sample sheet. The first line is a header naming the
+/// columns `path` and `sample`, and optionally `haplotype` (or `hap`,
+/// default 0) and `rename` (a two-column contig rename table). Lines
+/// starting with '#' are comments.
+pub fn parse_sample_sheet(path: &Path) -> crate::Result<Vec<SampleSheetRecord>> {
+    let invalid = |line_number: usize, msg: String| {
+        Error::Format(format!("{}:{}: {}", path.display(), line_number + 1, msg))
+    };
+
+    let reader = BufReader::new(File::open(path)?);
+    let mut lines = reader
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| line.as_ref().map_or(true, |l| !l.trim().is_empty() && !l.starts_with('#')));
+
+    let Some((header_number, header)) = lines.next() else {
+        return Err(invalid(0, "empty sample sheet".to_string()));
+    };
+    let header = header?;
+    let delimiter = if header.contains('\t') { '\t' } else { ',' };
+    let columns: Vec<String> = header.split(delimiter).map(|c| c.trim().to_ascii_lowercase()).collect();
+    let column = |names: &[&str]| columns.iter().position(|c| names.contains(&c.as_str()));
+    let path_column = column(&["path"]).ok_or_else(|| invalid(header_number, "missing column 'path'".to_string()))?;
+    let sample_column = column(&["sample"]).ok_or_else(|| invalid(header_number, "missing column 'sample'".to_string()))?;
+    let haplotype_column = column(&["haplotype", "hap"]);
+    let rename_column = column(&["rename"]);
+
+    let mut records = vec![];
+    for (line_number, line) in lines {
+        let line = line?;
+        let fields: Vec<&str> = line.split(delimiter).map(str::trim).collect();
+        let field = |index: Option<usize>| index.and_then(|i| fields.get(i).copied()).filter(|f| !f.is_empty());
+
+        let genome_path = PathBuf::from(field(Some(path_column)).ok_or_else(|| invalid(line_number, "empty path".to_string()))?);
+        if !genome_path.exists() {
+            return Err(invalid(line_number, format!("{:?} does not exist", genome_path)));
+        }
+        let sample = field(Some(sample_column)).ok_or_else(|| invalid(line_number, "empty sample".to_string()))?;
+        let haplotype = match field(haplotype_column) {
+            Some(hap) => parse_haplotype(hap).ok_or_else(|| invalid(line_number, format!("haplotype '{}' is not an integer", hap)))?,
+            None => 0,
+        };
+        let contig_names = field(rename_column).map(|rename| load_rename_map(Path::new(rename))).transpose()?;
+
+        records.push(SampleSheetRecord {
+            path: genome_path,
+            sample: sample.to_string(),
+            haplotype,
+            contig_names,
+        });
+    }
+    Ok(records)
+}
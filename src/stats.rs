@@ -0,0 +1,195 @@
+
+use std::path::PathBuf;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write, stdout};
+
+use needletail::errors::ParseErrorKind;
+use needletail::parse_fastx_reader;
+use serde_json::json;
+use crate::cmdline::*;
+use crate::error::Error;
+use crate::pansn::parse_pansn;
+use crate::reader::open_reader;
+
+/// Assembly statistics of one group of sequences.
+#[derive(Debug, Default, Clone)]
+pub struct AssemblyStats {
+    lengths: Vec<u64>,
+    gc_bases: u64,
+    acgt_bases: u64,
+    n_bases: u64,
+    gaps: u64,
+    longest: u64,
+    longest_name: String,
+}
+
+impl AssemblyStats {
+    pub fn add_sequence(&mut self, name: &str, seq: &[u8]) {
+        let mut in_gap = false;
+        for &base in seq {
+            match base {
+                b'G' | b'C' | b'g' | b'c' => {
+                    self.gc_bases += 1;
+                    self.acgt_bases += 1;
+                }
+                b'A' | b'T' | b'a' | b't' => self.acgt_bases += 1,
+                b'N' | b'n' => {
+                    self.n_bases += 1;
+                    if !in_gap {
+                        self.gaps += 1;
+                    }
+                }
+                _ => {}
+            }
+            in_gap = matches!(base, b'N' | b'n');
+        }
+        let length = seq.len() as u64;
+        if self.lengths.is_empty() || length > self.longest {
+            self.longest = length;
+            self.longest_name = name.to_string();
+        }
+        self.lengths.push(length);
+    }
+
+    pub fn sequences(&self) -> usize {
+        self.lengths.len()
+    }
+
+    pub fn total_length(&self) -> u64 {
+        self.lengths.iter().sum()
+    }
+
+    /// Nx and Lx: the length of the sequence that brings the sum of the
+    /// longest sequences to `percent` of the total, and how many it takes.
+    pub fn nx_lx(&self, percent: u64) -> (u64, usize) {
+        let mut lengths = self.lengths.clone();
+        lengths.sort_unstable_by(|a, b| b.cmp(a));
+        let target = self.total_length() * percent;
+        let mut sum = 0;
+        for (i, length) in lengths.iter().enumerate() {
+            sum += length;
+            if sum * 100 >= target {
+                return (*length, i + 1);
+            }
+        }
+        (0, 0)
+    }
+
+    /// G+C over the A, C, G and T bases, so gaps do not lower it.
+    pub fn gc_percent(&self) -> f64 {
+        percent(self.gc_bases, self.acgt_bases)
+    }
+
+    pub fn n_percent(&self) -> f64 {
+        percent(self.n_bases, self.total_length())
+    }
+}
+
+fn percent(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    (part as f64 * 10000.0 / total as f64).round() / 100.0
+}
+
+// Groups in the order they are first seen.
+#[derive(Default)]
+struct Groups {
+    index: HashMap<String, usize>,
+    stats: Vec<(String, AssemblyStats)>,
+}
+
+impl Groups {
+    fn get(&mut self, name: String) -> &mut AssemblyStats {
+        let i = *self.index.entry(name.clone()).or_insert_with(|| {
+            self.stats.push((name, AssemblyStats::default()));
+            self.stats.len() - 1
+        });
+        &mut self.stats[i].1
+    }
+}
+
+const COLUMNS: [&str; 12] = [
+    "name", "sequences", "total_length", "n50", "n90", "l50", "gc_percent", "n_bases", "n_percent", "gaps", "longest", "longest_name",
+];
+
+fn write_tsv(writer: &mut dyn Write, groups: &[(String, AssemblyStats)]) -> std::io::Result<()> {
+    writeln!(writer, "{}", COLUMNS.join("\t"))?;
+    for (name, stats) in groups {
+        let (n50, l50) = stats.nx_lx(50);
+        let (n90, _) = stats.nx_lx(90);
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{:.2}\t{}\t{:.2}\t{}\t{}\t{}",
+            name, stats.sequences(), stats.total_length(), n50, n90, l50, stats.gc_percent(),
+            stats.n_bases, stats.n_percent(), stats.gaps, stats.longest, stats.longest_name,
+        )?;
+    }
+    Ok(())
+}
+
+fn write_json(writer: &mut dyn Write, groups: &[(String, AssemblyStats)]) -> std::io::Result<()> {
+    let rows: Vec<_> = groups
+        .iter()
+        .map(|(name, stats)| {
+            let (n50, l50) = stats.nx_lx(50);
+            let (n90, _) = stats.nx_lx(90);
+            json!({
+                "name": name,
+                "sequences": stats.sequences(),
+                "total_length": stats.total_length(),
+                "n50": n50,
+                "n90": n90,
+                "l50": l50,
+                "gc_percent": stats.gc_percent(),
+                "n_bases": stats.n_bases,
+                "n_percent": stats.n_percent(),
+                "gaps": stats.gaps,
+                "longest": stats.longest,
+                "longest_name": stats.longest_name,
+            })
+        })
+        .collect();
+    serde_json::to_writer_pretty(&mut *writer, &rows)?;
+    writeln!(writer)
+}
+
+pub fn stats(args: StatsArgs) -> crate::Result<()> {
+    let mut groups = Groups::default();
+    for file in &args.input_files {
+        let file_name = file.display().to_string();
+        let mut reader = match parse_fastx_reader(open_reader(file)?) {
+            Ok(reader) => reader,
+            // An empty genome, as in fastixe: a row of zeros.
+            Err(e) if e.kind == ParseErrorKind::EmptyFile => {
+                groups.get(file_name);
+                continue;
+            }
+            Err(e) => return Err(Error::Format(format!("{}: {}", file_name, e))),
+        };
+        while let Some(record) = reader.next() {
+            let record = record.map_err(|e| Error::Format(format!("{}: {}", file_name, e)))?;
+            let id = String::from_utf8_lossy(record.id());
+            let name = id.split_whitespace().next().unwrap_or("");
+            // Names that are not PanSN names are counted for their file.
+            let group = match (args.by, parse_pansn(name, args.delimiter)) {
+                (StatsGroup::Sample, Some(pansn)) => pansn.sample.to_string(),
+                (StatsGroup::Haplotype, Some(pansn)) => format!("{}{}{}", pansn.sample, args.delimiter, pansn.haplotype),
+                _ => file_name.clone(),
+            };
+            groups.get(group).add_sequence(name, &record.seq());
+        }
+    }
+
+    let mut writer: BufWriter<Box<dyn Write>> = BufWriter::new(match &args.output {
+        Some(path) if path != &PathBuf::from("-") => Box::new(File::create(path)?),
+        _ => Box::new(stdout().lock()),
+    });
+    match args.format {
+        StatsFormat::Tsv => write_tsv(&mut writer, &groups.stats)?,
+        StatsFormat::Json => write_json(&mut writer, &groups.stats)?,
+    }
+    writer.flush()?;
+    Ok(())
+}
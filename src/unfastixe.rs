@@ -0,0 +1,110 @@
+
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::fs::{File, create_dir_all};
+use std::io::{BufRead, BufWriter, Write};
+
+use crate::cmdline::*;
+use crate::bgzf::BgzfWriter;
+use crate::error::Error;
+use crate::pansn::parse_pansn;
+use crate::reader::open_reader;
+use log::*;
+
+enum SplitWriter {
+    Plain(BufWriter<File>),
+    Bgzf(BgzfWriter<BufWriter<File>>),
+}
+
+impl SplitWriter {
+    fn create(path: &Path, args: &UnfastixeArgs) -> std::io::Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        if args.gzip_output {
+            Ok(SplitWriter::Bgzf(BgzfWriter::new(file, args.compression_level, args.threads)))
+        } else {
+            Ok(SplitWriter::Plain(file))
+        }
+    }
+
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            SplitWriter::Plain(writer) => writer,
+            SplitWriter::Bgzf(writer) => writer,
+        }
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        match self {
+            SplitWriter::Plain(writer) => writer.flush(),
+            SplitWriter::Bgzf(writer) => writer.finish(),
+        }
+    }
+}
+
+/// `HG002.fa` for `--by sample`, `HG002.hap1.fa` for `--by haplotype`, so
+/// the files can be fed back to fastixe.
+fn split_output_path(args: &UnfastixeArgs, sample: &str, haplotype: &str) -> PathBuf {
+    let stem = match args.by {
+        SplitBy::Sample => sample.to_string(),
+        SplitBy::Haplotype => format!("{}.hap{}", sample, haplotype),
+    };
+    let ext = if args.gzip_output { "fa.gz" } else { "fa" };
+    args.out_directory.join(format!("{}.{}", stem, ext))
+}
+
+pub fn unfastixe(args: UnfastixeArgs) -> crate::Result<()> {
+    // The haplotypes of a sample would share a file and clash once their
+    // prefixes are gone.
+    if args.strip_prefix && args.by == SplitBy::Sample {
+        return Err(Error::Args("--strip-prefix cannot be used with --by sample, the haplotypes would get the same names.".to_string()));
+    }
+    if args.compression_level.is_some_and(|level| level > 9) {
+        return Err(Error::Args("The compression level must be between 0 and 9.".to_string()));
+    }
+    create_dir_all(&args.out_directory)?;
+
+    let mut reader = open_reader(&args.input_file)?;
+    // Files are opened on first use and kept open, since the records of a
+    // sample do not have to be next to each other.
+    let mut writers: HashMap<PathBuf, SplitWriter> = HashMap::new();
+    let mut current: Option<PathBuf> = None;
+    let mut line = vec![];
+    let mut line_number = 0;
+    while reader.read_until(b'\n', &mut line)? > 0 {
+        line_number += 1;
+        if line.starts_with(b">") {
+            let header = String::from_utf8_lossy(&line[1..]).into_owned();
+            let name = header.split_whitespace().next().unwrap_or("");
+            let pansn = parse_pansn(name, args.delimiter).ok_or_else(|| Error::Naming(
+                format!("{}:{}: '{}' is not a PanSN name (sample{3}haplotype{3}contig)", args.input_file.display(), line_number, name, args.delimiter),
+            ))?;
+            let path = split_output_path(&args, pansn.sample, pansn.haplotype);
+            if !writers.contains_key(&path) {
+                writers.insert(path.clone(), SplitWriter::create(&path, &args)?);
+            }
+            let writer = writers.get_mut(&path).unwrap().writer();
+            if args.strip_prefix {
+                // The contig and anything after it, including the line end.
+                let contig_start = header.len() - header.trim_start().len() + name.len() - pansn.contig.len();
+                writer.write_all(b">")?;
+                writer.write_all(&header.as_bytes()[contig_start..])?;
+            } else {
+                writer.write_all(&line)?;
+            }
+            current = Some(path);
+        } else if let Some(path) = current.as_ref() {
+            writers.get_mut(path).unwrap().writer().write_all(&line)?;
+        } else if !line.trim_ascii().is_empty() {
+            return Err(Error::Format(
+                format!("{}:{}: sequence data found before the first FASTA header", args.input_file.display(), line_number),
+            ));
+        }
+        line.clear();
+    }
+
+    for writer in writers.values_mut() {
+        writer.finish()?;
+    }
+    info!("{}: wrote {} files to {}", args.input_file.display(), writers.len(), args.out_directory.display());
+    Ok(())
+}
@@ -1,5 +1,9 @@
 
+// The original tests pass their arguments as `&[...]`.
+#![allow(clippy::needless_borrows_for_generic_args)]
+
 use assert_cmd::Command;
+use std::io::{Read, Write};
 
 // tests/GCF_002012065.1_ASM201206v1_genomic.fna
 // tests/GCF_006400955.1_ASM640095v1_genomic.fna
@@ -70,12 +74,15 @@ fn test_cli5() {
 // gzip output
 #[test]
 fn test_cli6() {
+    // Not the default out dir: genomes/*.fna.gz are input fixtures for test_cli14.
+    let out_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_cli6");
     let mut cmd = Command::cargo_bin("panutils").unwrap();
     cmd.args(&[
         "fastixe", 
         "-d", "tests/",
         "-g",
         "--up",
+        "-o", out_dir.to_str().unwrap(),
     ])
     .assert()
     .success();
@@ -83,8 +90,8 @@ fn test_cli6() {
 
 // merge and bgzip output
 #[test]
-#[cfg(feature = "c_ffi")]
 fn test_cli7() {
+    let out_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_cli7");
     let mut cmd = Command::cargo_bin("panutils").unwrap();
     cmd.args(&[
         "fastixe", 
@@ -92,6 +99,7 @@ fn test_cli7() {
         "-m",
         "-b",
         "--up",
+        "-o", out_dir.to_str().unwrap(),
     ])
     .assert()
     .success();
@@ -99,8 +107,8 @@ fn test_cli7() {
 
 // merge and bgzip output and faidx
 #[test]
-#[cfg(feature = "c_ffi")]
 fn test_cli8() {
+    let out_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_cli8");
     let mut cmd = Command::cargo_bin("panutils").unwrap();
     cmd.args(&[
         "fastixe", 
@@ -109,6 +117,7 @@ fn test_cli8() {
         "-b",
         "-f",
         "--up",
+        "-o", out_dir.to_str().unwrap(),
     ])
     .assert()
     .success();
@@ -127,3 +136,1241 @@ fn test_cli9() {
     .assert()
     .success();
 }
+
+// native bgzip merge output is a valid BGZF file
+#[test]
+fn test_cli10() {
+    let out_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_cli10");
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args([
+        "fastixe", 
+        "-d", "tests/",
+        "-m",
+        "-b",
+        "--up",
+        "-o", out_dir.to_str().unwrap(),
+    ])
+    .assert()
+    .success();
+
+    let bgzf = std::fs::read(out_dir.join("merged.gz")).unwrap();
+    assert_eq!(&bgzf[..4], &[0x1f, 0x8b, 0x08, 0x04]);
+    assert_eq!(&bgzf[12..14], b"BC");
+    assert_eq!(&bgzf[bgzf.len() - 28..], &panutils::bgzf::BGZF_EOF);
+
+    let mut merged = String::new();
+    flate2::read::MultiGzDecoder::new(&bgzf[..]).read_to_string(&mut merged).unwrap();
+    assert!(merged.starts_with(">GCF_"));
+    assert_eq!(merged.matches('>').count(), 2);
+}
+
+// faidx built while writing the merge, for plain and bgzip output
+#[test]
+fn test_cli11() {
+    let out_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_cli11");
+    for bgzip in [false, true] {
+        let mut cmd = Command::cargo_bin("panutils").unwrap();
+        cmd.args([
+            "fastixe", 
+            "-d", "tests/",
+            "-m",
+            "-f",
+            "-o", out_dir.to_str().unwrap(),
+        ]);
+        if bgzip {
+            cmd.arg("-b");
+        }
+        cmd.assert().success();
+
+        let (merged_path, merged) = if bgzip {
+            let mut merged = String::new();
+            let bgzf = std::fs::read(out_dir.join("merged.gz")).unwrap();
+            flate2::read::MultiGzDecoder::new(&bgzf[..]).read_to_string(&mut merged).unwrap();
+            assert_eq!(std::fs::read(out_dir.join("merged.gz.gzi")).unwrap(), 0u64.to_le_bytes());
+            (out_dir.join("merged.gz"), merged)
+        } else {
+            (out_dir.join("merged.fa"), std::fs::read_to_string(out_dir.join("merged.fa")).unwrap())
+        };
+
+        let fai = std::fs::read_to_string(format!("{}.fai", merged_path.display())).unwrap();
+        assert_eq!(fai.lines().count(), 2);
+        for line in fai.lines() {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let length: usize = fields[1].parse().unwrap();
+            let offset: usize = fields[2].parse().unwrap();
+            assert!(merged[..offset].ends_with(&format!(">{}\n", fields[0])));
+            assert_eq!(merged[offset..].lines().take_while(|l| !l.starts_with('>')).map(str::len).sum::<usize>(), length);
+        }
+    }
+}
+
+fn bgzf_blocks(bgzf: &[u8]) -> Vec<&[u8]> {
+    let mut blocks = vec![];
+    let mut pos = 0;
+    while pos < bgzf.len() {
+        assert_eq!(&bgzf[pos..pos + 4], &[0x1f, 0x8b, 0x08, 0x04]);
+        assert_eq!(&bgzf[pos + 12..pos + 14], b"BC");
+        let block_size = u16::from_le_bytes([bgzf[pos + 16], bgzf[pos + 17]]) as usize + 1;
+        blocks.push(&bgzf[pos..pos + block_size]);
+        pos += block_size;
+    }
+    blocks
+}
+
+// bgzip merge output honours --level and --threads
+#[test]
+fn test_cli12() {
+    let out_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_cli12");
+    let in_dir = out_dir.join("input");
+    std::fs::create_dir_all(&in_dir).unwrap();
+    let mut fasta = String::from(">chr1\n");
+    let mut state: u32 = 1;
+    for i in 0..400_000 {
+        state = state.wrapping_mul(1103515245).wrapping_add(12345);
+        fasta.push(b"ACGT"[(state >> 16) as usize % 4] as char);
+        if i % 60 == 59 {
+            fasta.push('\n');
+        }
+    }
+    fasta.push('\n');
+    std::fs::write(in_dir.join("GCF_000000001.1_test.fa"), &fasta).unwrap();
+
+    let run = |level: &str, threads: &str| {
+        let name = format!("merged_{}_{}.fa", level, threads);
+        let mut cmd = Command::cargo_bin("panutils").unwrap();
+        cmd.args([
+            "fastixe", 
+            "-d", in_dir.to_str().unwrap(),
+            "-m",
+            "-b",
+            "--level", level,
+            "-t", threads,
+            "-e", &name,
+            "-o", out_dir.to_str().unwrap(),
+        ])
+        .assert()
+        .success();
+        std::fs::read(out_dir.join(name.replace(".fa", ".gz"))).unwrap()
+    };
+
+    let stored = run("0", "4");
+    let best = run("9", "4");
+    assert_eq!(best, run("9", "1"));
+    assert!(best.len() < stored.len() / 3);
+
+    for (bgzf, level) in [(&stored, 0), (&best, 9)] {
+        let blocks = bgzf_blocks(bgzf);
+        assert!(blocks.len() > 4);
+        assert_eq!(blocks.last().unwrap(), &&panutils::bgzf::BGZF_EOF[..]);
+        for block in &blocks[..blocks.len() - 1] {
+            // BTYPE 00 is a stored (level 0) deflate block.
+            let stored_block = block[18] & 0x06 == 0;
+            assert_eq!(stored_block, level == 0);
+        }
+        let mut merged = String::new();
+        flate2::read::MultiGzDecoder::new(&bgzf[..]).read_to_string(&mut merged).unwrap();
+        assert_eq!(merged, fasta.replacen(">chr1", ">GCF_000000001.1#0#chr1", 1));
+    }
+}
+
+// gzip output is one BGZF stream, identical to the plain output once decompressed
+#[test]
+fn test_cli13() {
+    let out_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_cli13");
+    for gzip in [false, true] {
+        let mut cmd = Command::cargo_bin("panutils").unwrap();
+        cmd.args([
+            "fastixe", 
+            "-i", "tests/GCF_002012065.1_ASM201206v1_genomic.fna",
+            "-t", "2",
+            "-o", out_dir.to_str().unwrap(),
+        ]);
+        if gzip {
+            cmd.arg("-g");
+        }
+        cmd.assert().success();
+    }
+
+    let plain = std::fs::read_to_string(out_dir.join("GCF_002012065.1_ASM201206v1_genomic.fna")).unwrap();
+    let gzip = std::fs::read(out_dir.join("GCF_002012065.1_ASM201206v1_genomic.fna.gz")).unwrap();
+    let blocks = bgzf_blocks(&gzip);
+    assert_eq!(blocks.len(), 2);
+    assert_eq!(blocks[1], &panutils::bgzf::BGZF_EOF[..]);
+
+    let mut decompressed = String::new();
+    flate2::read::GzDecoder::new(&gzip[..]).read_to_string(&mut decompressed).unwrap();
+    assert_eq!(decompressed, plain);
+}
+
+// gzipped inputs are decompressed, in both the separate and merge paths
+#[test]
+fn test_cli14() {
+    let out_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_cli14");
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args([
+        "fastixe", 
+        "-i", "genomes/GCF_002012065.1_ASM201206v1_genomic.fna.gz",
+        "-p", "",
+        "-o", out_dir.to_str().unwrap(),
+    ])
+    .assert()
+    .success();
+
+    let gzip = std::fs::read("genomes/GCF_002012065.1_ASM201206v1_genomic.fna.gz").unwrap();
+    let mut expected = String::new();
+    flate2::read::MultiGzDecoder::new(&gzip[..]).read_to_string(&mut expected).unwrap();
+    let output = std::fs::read_to_string(out_dir.join("GCF_002012065.1_ASM201206v1_genomic.fna")).unwrap();
+    assert_eq!(output, expected);
+
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args([
+        "fastixe", 
+        "-s", "genomes/GCF_002012065.1_ASM201206v1_genomic.fna.gz", "genomes/GCF_006400955.1_ASM640095v1_genomic.fna.gz",
+        "-m",
+        "-o", out_dir.to_str().unwrap(),
+    ])
+    .assert()
+    .success();
+
+    let merged = std::fs::read_to_string(out_dir.join("merged.fa")).unwrap();
+    assert_eq!(merged.matches('>').count(), 2);
+    assert!(merged.contains(&expected.lines().nth(1).unwrap()[..40]));
+}
+
+// zstd, bzip2 and xz inputs are detected from their content
+#[test]
+fn test_cli15() {
+    let out_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_cli15");
+    let in_dir = out_dir.join("input");
+    std::fs::create_dir_all(&in_dir).unwrap();
+    let fasta = std::fs::read("tests/GCF_002012065.1_ASM201206v1_genomic.fna").unwrap();
+
+    let mut zst = zstd::stream::write::Encoder::new(vec![], 3).unwrap();
+    zst.write_all(&fasta).unwrap();
+    std::fs::write(in_dir.join("GCF_000000001.1_zst.fna.zst"), zst.finish().unwrap()).unwrap();
+    let mut bz2 = bzip2::write::BzEncoder::new(vec![], bzip2::Compression::default());
+    bz2.write_all(&fasta).unwrap();
+    std::fs::write(in_dir.join("GCF_000000002.1_bz2.fa.bz2"), bz2.finish().unwrap()).unwrap();
+    let mut xz = liblzma::write::XzEncoder::new(vec![], 6);
+    xz.write_all(&fasta).unwrap();
+    std::fs::write(in_dir.join("GCF_000000003.1_xz.fasta.xz"), xz.finish().unwrap()).unwrap();
+
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args([
+        "fastixe", 
+        "-d", in_dir.to_str().unwrap(),
+        "-o", out_dir.to_str().unwrap(),
+    ])
+    .assert()
+    .success();
+
+    let expected_seq = String::from_utf8(fasta).unwrap().split_once('\n').unwrap().1.to_string();
+    for (name, prefix) in [
+        ("GCF_000000001.1_zst.fna", "GCF_000000001.1#0#"),
+        ("GCF_000000002.1_bz2.fa", "GCF_000000002.1#0#"),
+        ("GCF_000000003.1_xz.fasta", "GCF_000000003.1#0#"),
+    ] {
+        let output = std::fs::read_to_string(out_dir.join(name)).unwrap();
+        let (header, seq) = output.split_once('\n').unwrap();
+        assert_eq!(header, format!(">{}NZ_CP018979.1", prefix));
+        assert_eq!(seq, expected_seq);
+    }
+
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args([
+        "fastixe", 
+        "-d", in_dir.to_str().unwrap(),
+        "-m",
+        "-o", out_dir.to_str().unwrap(),
+    ])
+    .assert()
+    .success();
+    let merged = std::fs::read_to_string(out_dir.join("merged.fa")).unwrap();
+    assert_eq!(merged.matches(">GCF_00000000").count(), 3);
+}
+
+// merge output follows the input order, or file names with --sort name
+#[test]
+fn test_cli16() {
+    let out_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_cli16");
+    let headers = |sort: &str| {
+        let mut cmd = Command::cargo_bin("panutils").unwrap();
+        cmd.args([
+            "fastixe", 
+            "-s", "tests/GCF_006400955.1_ASM640095v1_genomic.fna", "tests/GCF_002012065.1_ASM201206v1_genomic.fna",
+            "-m",
+            "-t", "4",
+            "--sort", sort,
+            "-o", out_dir.to_str().unwrap(),
+        ])
+        .assert()
+        .success();
+        let merged = std::fs::read_to_string(out_dir.join("merged.fa")).unwrap();
+        merged.lines().filter(|l| l.starts_with('>')).map(String::from).collect::<Vec<_>>()
+    };
+
+    for _ in 0..5 {
+        assert_eq!(headers("input"), [">GCF_006400955.1#0#NZ_CP017169.1", ">GCF_002012065.1#0#NZ_CP018979.1"]);
+        assert_eq!(headers("name"), [">GCF_002012065.1#0#NZ_CP018979.1", ">GCF_006400955.1#0#NZ_CP017169.1"]);
+    }
+}
+
+// bounded streaming merge keeps every record, in input order
+#[test]
+fn test_cli17() {
+    let out_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_cli17");
+    let in_dir = out_dir.join("input");
+    std::fs::create_dir_all(&in_dir).unwrap();
+    let mut expected = vec![];
+    for genome in 0..8 {
+        let mut fasta = String::new();
+        for contig in 0..10 {
+            fasta.push_str(&format!(">ctg{}\n{}\n", contig, "ACGT".repeat(100 * (genome + 1))));
+            expected.push(format!(">GCF_00000000{}.1#0#ctg{}", genome, contig));
+        }
+        std::fs::write(in_dir.join(format!("GCF_00000000{}.1_test.fa", genome)), fasta).unwrap();
+    }
+
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args([
+        "fastixe", 
+        "-d", in_dir.to_str().unwrap(),
+        "-m",
+        "-b",
+        "-t", "3",
+        "--channel-capacity", "1",
+        "-o", out_dir.to_str().unwrap(),
+    ])
+    .assert()
+    .success();
+
+    let bgzf = std::fs::read(out_dir.join("merged.gz")).unwrap();
+    let mut merged = String::new();
+    flate2::read::MultiGzDecoder::new(&bgzf[..]).read_to_string(&mut merged).unwrap();
+    let headers: Vec<&str> = merged.lines().filter(|l| l.starts_with('>')).collect();
+    assert_eq!(headers, expected);
+}
+
+// --line-width rewraps sequences the same way in the separate and merge paths
+#[test]
+fn test_cli18() {
+    let out_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_cli18");
+    let input = "tests/GCF_002012065.1_ASM201206v1_genomic.fna";
+    let seq: String = std::fs::read_to_string(input).unwrap().lines().skip(1).collect();
+
+    for width in [0, 50] {
+        let separate_dir = out_dir.join(format!("separate_{}", width));
+        let mut cmd = Command::cargo_bin("panutils").unwrap();
+        cmd.args([
+            "fastixe", 
+            "-i", input,
+            "--line-width", &width.to_string(),
+            "-o", separate_dir.to_str().unwrap(),
+        ])
+        .assert()
+        .success();
+
+        let merge_dir = out_dir.join(format!("merge_{}", width));
+        let mut cmd = Command::cargo_bin("panutils").unwrap();
+        cmd.args([
+            "fastixe", 
+            "-i", input,
+            "-m",
+            "--line-width", &width.to_string(),
+            "-o", merge_dir.to_str().unwrap(),
+        ])
+        .assert()
+        .success();
+
+        let separate = std::fs::read_to_string(separate_dir.join("GCF_002012065.1_ASM201206v1_genomic.fna")).unwrap();
+        let merged = std::fs::read_to_string(merge_dir.join("merged.fa")).unwrap();
+        assert_eq!(separate, merged);
+
+        let lines: Vec<&str> = separate.lines().skip(1).collect();
+        assert_eq!(lines.concat(), seq);
+        if width == 0 {
+            assert_eq!(lines.len(), 1);
+        } else {
+            assert!(lines.iter().all(|l| l.len() <= width));
+            assert!(lines[..lines.len() - 1].iter().all(|l| l.len() == width));
+        }
+    }
+}
+
+// --keep-description and --description-table
+#[test]
+fn test_cli19() {
+    let out_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_cli19");
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args([
+        "fastixe", 
+        "-i", "tests/GCF_002012065.1_ASM201206v1_genomic.fna",
+        "--keep-description",
+        "--description-table",
+        "-o", out_dir.to_str().unwrap(),
+    ])
+    .assert()
+    .success();
+
+    let output = std::fs::read_to_string(out_dir.join("GCF_002012065.1_ASM201206v1_genomic.fna")).unwrap();
+    assert_eq!(output.lines().next().unwrap(), ">GCF_002012065.1#0#NZ_CP018979.1 Escherichia coli strain Ecol_656 chromosome, complete genome");
+    let table = std::fs::read_to_string(out_dir.join("GCF_002012065.1_ASM201206v1_genomic.fna.descriptions.tsv")).unwrap();
+    assert_eq!(table, "GCF_002012065.1#0#NZ_CP018979.1\tEscherichia coli strain Ecol_656 chromosome, complete genome\n");
+
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args([
+        "fastixe", 
+        "-d", "tests/",
+        "-m",
+        "--description-table",
+        "-o", out_dir.to_str().unwrap(),
+    ])
+    .assert()
+    .success();
+
+    let merged = std::fs::read_to_string(out_dir.join("merged.fa")).unwrap();
+    assert!(merged.starts_with(">GCF_002012065.1#0#NZ_CP018979.1\n"));
+    let table = std::fs::read_to_string(out_dir.join("merged.fa.descriptions.tsv")).unwrap();
+    assert_eq!(table.lines().collect::<Vec<_>>(), [
+        "GCF_002012065.1#0#NZ_CP018979.1\tEscherichia coli strain Ecol_656 chromosome, complete genome",
+        "GCF_006400955.1#0#NZ_CP017169.1\tMyxococcus xanthus strain GH3.5.6c2 chromosome, complete genome",
+    ]);
+}
+
+// haplotype from named capture groups or from common phased file names
+#[test]
+fn test_cli20() {
+    let out_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_cli20");
+    let in_dir = out_dir.join("input");
+    std::fs::create_dir_all(&in_dir).unwrap();
+    for name in ["HG002.hap1.fa", "HG003.paternal.fa", "HG004.mat.fa", "HG005.fa"] {
+        std::fs::write(in_dir.join(name), ">chr1\nACGT\n").unwrap();
+    }
+    std::fs::write(in_dir.join("NA12878.h2.fa"), ">chr1\nACGT\n").unwrap();
+
+    let headers = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin("panutils").unwrap();
+        cmd.args(["fastixe", "-m", "-o", out_dir.to_str().unwrap()])
+            .args(args)
+            .assert()
+            .success();
+        let merged = std::fs::read_to_string(out_dir.join("merged.fa")).unwrap();
+        merged.lines().filter(|l| l.starts_with('>')).map(String::from).collect::<Vec<_>>()
+    };
+
+    let inputs: Vec<String> = ["HG002.hap1.fa", "HG003.paternal.fa", "HG004.mat.fa", "HG005.fa"]
+        .iter()
+        .map(|name| in_dir.join(name).to_string_lossy().to_string())
+        .collect();
+    let mut args = vec!["-r", "^[^.]+", "-s"];
+    args.extend(inputs.iter().map(String::as_str));
+    assert_eq!(headers(&args), [">HG002#1#chr1", ">HG003#1#chr1", ">HG004#2#chr1", ">HG005#0#chr1"]);
+
+    let input = in_dir.join("NA12878.h2.fa");
+    assert_eq!(
+        headers(&["-r", r"(?P<sample>[^.]+)\.h(?P<hap>\d+)", "-i", input.to_str().unwrap()]),
+        [">NA12878#2#chr1"],
+    );
+}
+
+// sample sheet with sample, haplotype and a per-genome contig rename table
+#[test]
+fn test_cli21() {
+    let out_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_cli21");
+    std::fs::create_dir_all(&out_dir).unwrap();
+    let rename = out_dir.join("rename.tsv");
+    std::fs::write(&rename, "NZ_CP018979.1\tchr1\n").unwrap();
+    let sheet = out_dir.join("samples.csv");
+    std::fs::write(&sheet, format!(
+        "path,sample,haplotype,rename\ntests/GCF_002012065.1_ASM201206v1_genomic.fna,ecoli,2,{}\ntests/GCF_006400955.1_ASM640095v1_genomic.fna,mxan,,\n",
+        rename.display(),
+    )).unwrap();
+
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args([
+        "fastixe", 
+        "-S", sheet.to_str().unwrap(),
+        "-m",
+        "-o", out_dir.to_str().unwrap(),
+    ])
+    .assert()
+    .success();
+    let merged = std::fs::read_to_string(out_dir.join("merged.fa")).unwrap();
+    let headers: Vec<&str> = merged.lines().filter(|l| l.starts_with('>')).collect();
+    assert_eq!(headers, [">ecoli#2#chr1", ">mxan#0#NZ_CP017169.1"]);
+
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args([
+        "fastixe", 
+        "-S", sheet.to_str().unwrap(),
+        "-o", out_dir.to_str().unwrap(),
+    ])
+    .assert()
+    .success();
+    let output = std::fs::read_to_string(out_dir.join("GCF_002012065.1_ASM201206v1_genomic.fna")).unwrap();
+    assert!(output.starts_with(">ecoli#2#chr1\n"));
+
+    std::fs::write(&sheet, "path,sample\nmissing.fa,x\n").unwrap();
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args(["fastixe", "-S", sheet.to_str().unwrap(), "-o", out_dir.to_str().unwrap()])
+        .assert()
+        .failure();
+}
+
+// pansn-check
+#[test]
+fn test_cli22() {
+    let out_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_cli22");
+    std::fs::create_dir_all(&out_dir).unwrap();
+
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args([
+        "fastixe", 
+        "-d", "tests/",
+        "-m",
+        "-o", out_dir.to_str().unwrap(),
+    ])
+    .assert()
+    .success();
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args(["pansn-check", out_dir.join("merged.fa").to_str().unwrap()])
+        .assert()
+        .success();
+
+    let bad = out_dir.join("bad.fa");
+    std::fs::write(&bad, ">HG002#1#chr1\nA\n>HG002#1#chr1\nA\n>HG002#3#chr1\nA\n>HG003#a#chr1\nA\n>chr1\nA\n").unwrap();
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    let output = cmd.args(["pansn-check", bad.to_str().unwrap()]).output().unwrap();
+    assert!(!output.status.success());
+    let report = String::from_utf8(output.stdout).unwrap();
+    let kinds: Vec<&str> = report.lines().map(|l| l.split('\t').nth(1).unwrap()).collect();
+    assert_eq!(kinds, ["duplicate", "haplotype", "malformed", "haplotype-gap"]);
+
+    let gfa = out_dir.join("graph.gfa");
+    // chr1 of haplotype 2 is split across two walks.
+    std::fs::write(&gfa, "H\tVN:Z:1.1\nS\t1\tACGT\nP\tHG002|1|chr1\t1+\t*\nW\tHG002\t2\tchr1\t0\t4\t>1\nW\tHG002\t2\tchr1\t10\t14\t>1\n").unwrap();
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args(["pansn-check", "--delimiter", "|", gfa.to_str().unwrap()])
+        .assert()
+        .success();
+
+    let paf = out_dir.join("aln.paf");
+    std::fs::write(&paf, "HG002#1#chr1\t4\t0\t4\t+\tHG003#1#chr1\t4\t0\t4\t4\t4\t60\nHG002#1#chr1\t4\t0\t4\t+\tHG003#1#chr1\t4\t0\t4\t4\t4\t60\n").unwrap();
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args(["pansn-check", paf.to_str().unwrap()])
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_cli23() {
+    let out_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_cli23");
+    let in_dir = out_dir.join("inputs");
+    std::fs::create_dir_all(&in_dir).unwrap();
+    // Both file names give the prefix GCA_000001.1#0#.
+    std::fs::write(in_dir.join("GCA_000001.1_a.fa"), ">chr1\nACGT\n>chr2\nACGT\n").unwrap();
+    std::fs::write(in_dir.join("GCA_000001.1_b.fa"), ">chr1\nTTTT\n").unwrap();
+    let merged = out_dir.join("merged.fa");
+
+    let run = |policy: &str| {
+        let _ = std::fs::remove_file(&merged);
+        let mut cmd = Command::cargo_bin("panutils").unwrap();
+        cmd.args([
+            "fastixe",
+            "-d", in_dir.to_str().unwrap(),
+            "-m",
+            "-o", out_dir.to_str().unwrap(),
+            "--on-duplicate", policy,
+        ])
+        .output()
+        .unwrap()
+    };
+
+    let output = run("fail");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Duplicate sequence name 'GCA_000001.1#0#chr1'"));
+    assert!(!merged.exists());
+
+    assert!(run("suffix").status.success());
+    assert_eq!(
+        std::fs::read_to_string(&merged).unwrap(),
+        ">GCA_000001.1#0#chr1\nACGT\n>GCA_000001.1#0#chr2\nACGT\n>GCA_000001.1#0#chr1_1\nTTTT\n"
+    );
+
+    assert!(run("skip").status.success());
+    assert_eq!(
+        std::fs::read_to_string(&merged).unwrap(),
+        ">GCA_000001.1#0#chr1\nACGT\n>GCA_000001.1#0#chr2\nACGT\n"
+    );
+}
+
+#[test]
+fn test_cli24() {
+    let out_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_cli24");
+    std::fs::create_dir_all(&out_dir).unwrap();
+    let merged = out_dir.join("merged.fa");
+    std::fs::write(&merged, ">HG002#1#chr1 first\nACGT\nAC\n>HG002#2#chr1\nGGGG\n>HG003#1#chr#2\nTTTT\n>HG002#1#chr2\nCC\n").unwrap();
+
+    let split_dir = out_dir.join("haplotype");
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args(["unfastixe", merged.to_str().unwrap(), "-o", split_dir.to_str().unwrap(), "--strip-prefix"])
+        .assert()
+        .success();
+    assert_eq!(std::fs::read_to_string(split_dir.join("HG002.hap1.fa")).unwrap(), ">chr1 first\nACGT\nAC\n>chr2\nCC\n");
+    assert_eq!(std::fs::read_to_string(split_dir.join("HG002.hap2.fa")).unwrap(), ">chr1\nGGGG\n");
+    assert_eq!(std::fs::read_to_string(split_dir.join("HG003.hap1.fa")).unwrap(), ">chr#2\nTTTT\n");
+
+    let split_dir = out_dir.join("sample");
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args(["split", merged.to_str().unwrap(), "-o", split_dir.to_str().unwrap(), "--by", "sample", "--gz"])
+        .assert()
+        .success();
+    let bgzf = std::fs::read(split_dir.join("HG002.fa.gz")).unwrap();
+    assert!(bgzf.ends_with(&panutils::bgzf::BGZF_EOF));
+    let mut sample = String::new();
+    flate2::read::MultiGzDecoder::new(&bgzf[..]).read_to_string(&mut sample).unwrap();
+    assert_eq!(sample, ">HG002#1#chr1 first\nACGT\nAC\n>HG002#2#chr1\nGGGG\n>HG002#1#chr2\nCC\n");
+
+    let bad = out_dir.join("bad.fa");
+    std::fs::write(&bad, ">chr1\nACGT\n").unwrap();
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args(["unfastixe", bad.to_str().unwrap(), "-o", out_dir.join("bad").to_str().unwrap()])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_cli25() {
+    let out_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_cli25");
+    let in_dir = out_dir.join("inputs");
+    std::fs::create_dir_all(&in_dir).unwrap();
+    std::fs::write(in_dir.join("HG002.hap1.fa"), ">chr1\nACGT\n>HG003#1#chr2\nACGT\n").unwrap();
+
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args([
+        "fastixe",
+        "-i", in_dir.join("HG002.hap1.fa").to_str().unwrap(),
+        "-r", "^[^.]+",
+        "--delimiter", ".",
+        "-o", out_dir.to_str().unwrap(),
+    ])
+    .assert()
+    .success();
+    assert_eq!(
+        std::fs::read_to_string(out_dir.join("HG002.hap1.fa")).unwrap(),
+        ">HG002.1.chr1\nACGT\n>HG002.1.HG003#1#chr2\nACGT\n"
+    );
+
+    // Existing PanSN names are converted, the others are prefixed.
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args([
+        "fastixe",
+        "-d", in_dir.to_str().unwrap(),
+        "-r", "^[^.]+",
+        "--delimiter", "|",
+        "--convert-delimiter", "#",
+        "-m",
+        "-o", out_dir.to_str().unwrap(),
+    ])
+    .assert()
+    .success();
+    assert_eq!(
+        std::fs::read_to_string(out_dir.join("merged.fa")).unwrap(),
+        ">HG002|1|chr1\nACGT\n>HG003|1|chr2\nACGT\n"
+    );
+
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args(["pansn-check", "--delimiter", "|", out_dir.join("merged.fa").to_str().unwrap()])
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_cli26() {
+    let out_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_cli26");
+    let in_dir = out_dir.join("inputs");
+    std::fs::create_dir_all(&in_dir).unwrap();
+    let genome = in_dir.join("HG002.hap1.fa");
+    std::fs::write(&genome, ">NC_000001.11 Homo sapiens chromosome 1\nACGT\n>CM000664.2\nACGT\n>NT_187361.1\nACGT\n>contig_9\nACGT\n").unwrap();
+
+    let report = out_dir.join("GCF_000001405.40_GRCh38.p14_assembly_report.txt");
+    std::fs::write(&report, "\
+# Assembly name:  GRCh38.p14
+# Sequence-Name\tSequence-Role\tAssigned-Molecule\tAssigned-Molecule-Location/Type\tGenBank-Accn\tRelationship\tRefSeq-Accn\tAssembly-Unit\tSequence-Length\tUCSC-style-name
+1\tassembled-molecule\t1\tChromosome\tCM000663.2\t=\tNC_000001.11\tPrimary Assembly\t248956422\tchr1
+2\tassembled-molecule\t2\tChromosome\tCM000664.2\t=\tNC_000002.12\tPrimary Assembly\t242193529\tchr2
+HSCHR1_CTG1_UNLOCALIZED\tunlocalized-scaffold\t1\tChromosome\tKI270706.1\t=\tNT_187361.1\tPrimary Assembly\t175055\tna
+").unwrap();
+
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    let output = cmd.args([
+        "fastixe",
+        "-i", genome.to_str().unwrap(),
+        "-r", "^[^.]+",
+        "--rename-map", report.to_str().unwrap(),
+        "-o", out_dir.to_str().unwrap(),
+    ])
+    .output()
+    .unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("1 contigs in"));
+    assert_eq!(
+        std::fs::read_to_string(out_dir.join("HG002.hap1.fa")).unwrap(),
+        ">HG002#1#chr1\nACGT\n>HG002#1#chr2\nACGT\n>HG002#1#HSCHR1_CTG1_UNLOCALIZED\nACGT\n>HG002#1#contig_9\nACGT\n"
+    );
+
+    // The two-column table works in merge mode too.
+    let table = out_dir.join("rename.tsv");
+    std::fs::write(&table, "NC_000001.11\tchr1\ncontig_9\tchrUn_9\n").unwrap();
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args([
+        "fastixe",
+        "-d", in_dir.to_str().unwrap(),
+        "-r", "^[^.]+",
+        "--rename-map", table.to_str().unwrap(),
+        "-m",
+        "-o", out_dir.to_str().unwrap(),
+    ])
+    .assert()
+    .success();
+    assert_eq!(
+        std::fs::read_to_string(out_dir.join("merged.fa")).unwrap(),
+        ">HG002#1#chr1\nACGT\n>HG002#1#CM000664.2\nACGT\n>HG002#1#NT_187361.1\nACGT\n>HG002#1#chrUn_9\nACGT\n"
+    );
+
+    // Older reports have no UCSC-style-name column.
+    let report = out_dir.join("GCF_000001405.25_GRCh37.p13_assembly_report.txt");
+    std::fs::write(&report, "\
+# Assembly name:  GRCh37.p13
+# Sequence-Name\tSequence-Role\tAssigned-Molecule\tAssigned-Molecule-Location/Type\tGenBank-Accn\tRelationship\tRefSeq-Accn\tAssembly-Unit
+1\tassembled-molecule\t1\tChromosome\tCM000663.2\t=\tNC_000001.11\tPrimary Assembly
+").unwrap();
+    let old_dir = out_dir.join("old");
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args([
+        "fastixe",
+        "-i", genome.to_str().unwrap(),
+        "-r", "^[^.]+",
+        "--rename-map", report.to_str().unwrap(),
+        "-o", old_dir.to_str().unwrap(),
+    ])
+    .assert()
+    .success();
+    assert_eq!(
+        std::fs::read_to_string(old_dir.join("HG002.hap1.fa")).unwrap(),
+        ">HG002#1#1\nACGT\n>HG002#1#CM000664.2\nACGT\n>HG002#1#NT_187361.1\nACGT\n>HG002#1#contig_9\nACGT\n"
+    );
+}
+
+#[test]
+fn test_cli27() {
+    let out_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_cli27");
+    let in_dir = out_dir.join("inputs");
+    std::fs::create_dir_all(&in_dir).unwrap();
+    let genome = in_dir.join("HG002.hap1.fa");
+    std::fs::write(&genome, ">chr1 kept\nACGTACGT\nACGT\n>chr2 short\nACG\n>chrM\nACGTACGT\n>chr3_random\nACGTACGT\n>chr4 long\nACGTACGT\nACGTACGT\nACGT\n").unwrap();
+    let expected = ">HG002#1#chr1\nACGTACGT\nACGT\n";
+
+    let filters = ["--min-length", "4", "--max-length", "16", "--exclude-regex", "^chrM$|_random", "--include-regex", "^chr"];
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    let output = cmd.args(["fastixe", "-i", genome.to_str().unwrap(), "-r", "^[^.]+", "-o", out_dir.to_str().unwrap()])
+        .args(filters)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(std::fs::read_to_string(out_dir.join("HG002.hap1.fa")).unwrap(), expected);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("kept 1 contigs, dropped 2 by name and 2 by length (39 bp)"));
+
+    // The summary must not end up in the FASTA stream.
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    let output = cmd.args(["fastixe", "-i", genome.to_str().unwrap(), "-r", "^[^.]+", "--stdout"])
+        .args(filters)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args(["fastixe", "-d", in_dir.to_str().unwrap(), "-r", "^[^.]+", "-m", "-o", out_dir.to_str().unwrap()])
+        .args(filters)
+        .assert()
+        .success();
+    assert_eq!(std::fs::read_to_string(out_dir.join("merged.fa")).unwrap(), expected);
+
+    // Name filters alone keep streaming the records.
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args(["fastixe", "-i", genome.to_str().unwrap(), "-r", "^[^.]+", "-o", out_dir.to_str().unwrap(), "--exclude-regex", "chr[1-3]"])
+        .assert()
+        .success();
+    assert_eq!(
+        std::fs::read_to_string(out_dir.join("HG002.hap1.fa")).unwrap(),
+        ">HG002#1#chrM\nACGTACGT\n>HG002#1#chr4\nACGTACGT\nACGTACGT\nACGT\n"
+    );
+}
+
+#[test]
+fn test_cli28() {
+    let out_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_cli28");
+    std::fs::create_dir_all(&out_dir).unwrap();
+    let merged = out_dir.join("merged.fa");
+    std::fs::write(&merged, ">HG002#1#chr1\nACGTNNNNGG\n>HG002#1#chr2\nACNNA\n>HG002#2#chr1\nGGGG\n>plain\nAT\n").unwrap();
+
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    let output = cmd.args(["stats", "--by", "haplotype", merged.to_str().unwrap()]).output().unwrap();
+    assert!(output.status.success());
+    let merged_name = merged.to_str().unwrap();
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!("\
+name\tsequences\ttotal_length\tn50\tn90\tl50\tgc_percent\tn_bases\tn_percent\tgaps\tlongest\tlongest_name
+HG002#1\t2\t15\t10\t5\t1\t55.56\t6\t40.00\t2\t10\tHG002#1#chr1
+HG002#2\t1\t4\t4\t4\t1\t100.00\t0\t0.00\t0\t4\tHG002#2#chr1
+{merged_name}\t1\t2\t2\t2\t1\t0.00\t0\t0.00\t0\t2\tplain
+")
+    );
+
+    // Compressed inputs, one JSON row per file.
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    let output = cmd.args([
+        "stats", "--format", "json",
+        "tests/GCF_002012065.1_ASM201206v1_genomic.fna",
+        "genomes/GCF_002012065.1_ASM201206v1_genomic.fna.gz",
+    ])
+    .output()
+    .unwrap();
+    assert!(output.status.success());
+    let rows: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let rows = rows.as_array().unwrap();
+    assert_eq!(rows.len(), 2);
+    for key in ["sequences", "total_length", "n50", "n90", "l50", "gc_percent", "longest"] {
+        assert_eq!(rows[0][key], rows[1][key]);
+    }
+
+    // An empty file gets a row of zeros instead of an error.
+    let empty = out_dir.join("empty.fa");
+    std::fs::write(&empty, "").unwrap();
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    let output = cmd.args(["stats", empty.to_str().unwrap()]).output().unwrap();
+    assert!(output.status.success());
+    let empty_name = empty.to_str().unwrap();
+    assert!(String::from_utf8(output.stdout).unwrap().ends_with(&format!("\n{empty_name}\t0\t0\t0\t0\t0\t0.00\t0\t0.00\t0\t0\t\n")));
+}
+
+#[test]
+fn test_cli29() {
+    let out_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_cli29");
+    let in_dir = out_dir.join("inputs");
+    std::fs::create_dir_all(&in_dir).unwrap();
+    // Long enough for several BGZF blocks.
+    let mut state = 1u32;
+    let seq: Vec<u8> = (0..200_000)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            b"ACGTacgtN"[(state >> 16) as usize % 9]
+        })
+        .collect();
+    let mut fasta = b">chr1\n".to_vec();
+    for line in seq.chunks(70) {
+        fasta.extend_from_slice(line);
+        fasta.push(b'\n');
+    }
+    fasta.extend_from_slice(b">chr2\nACGTTT\n");
+    std::fs::write(in_dir.join("GCA_000001.1_x.fa"), &fasta).unwrap();
+
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args(["fastixe", "-d", in_dir.to_str().unwrap(), "-m", "-b", "-f", "-o", out_dir.to_str().unwrap()])
+        .assert()
+        .success();
+    let merged = out_dir.join("merged.gz");
+
+    let fetch = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin("panutils").unwrap();
+        let output = cmd.arg("faidx").args(args).output().unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let wrap = |seq: &[u8]| seq.chunks(60).map(|l| format!("{}\n", std::str::from_utf8(l).unwrap())).collect::<String>();
+
+    let region = "GCA_000001.1#0#chr1:130,001-130,150";
+    assert_eq!(
+        fetch(&[merged.to_str().unwrap(), region, "GCA_000001.1#0#chr2:2-3"]),
+        format!(">{}\n{}>GCA_000001.1#0#chr2:2-3\nCG\n", region, wrap(&seq[130_000..130_150]))
+    );
+    assert_eq!(fetch(&[merged.to_str().unwrap(), "-i", "GCA_000001.1#0#chr2:2-4"]), ">GCA_000001.1#0#chr2:2-4/rc\nACG\n");
+
+    // BED regions against the plain input; its .fai is built on the way.
+    let bed = out_dir.join("regions.bed");
+    std::fs::write(&bed, "chr1\t199990\t200000\nchr2\t0\t6\n").unwrap();
+    let plain = in_dir.join("GCA_000001.1_x.fa");
+    assert_eq!(
+        fetch(&[plain.to_str().unwrap(), "--bed", bed.to_str().unwrap()]),
+        format!(">chr1:199991-200000\n{}>chr2:1-6\nACGTTT\n", wrap(&seq[199_990..]))
+    );
+    assert!(in_dir.join("GCA_000001.1_x.fa.fai").exists());
+
+    // A missing .gzi is rebuilt from the BGZF block headers.
+    let gzi = std::fs::read(out_dir.join("merged.gz.gzi")).unwrap();
+    std::fs::remove_file(out_dir.join("merged.gz.gzi")).unwrap();
+    fetch(&[merged.to_str().unwrap(), region]);
+    assert_eq!(std::fs::read(out_dir.join("merged.gz.gzi")).unwrap(), gzi);
+}
+
+#[test]
+fn test_cli30() {
+    let out_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_cli30");
+    let in_dir = out_dir.join("inputs");
+    std::fs::create_dir_all(&in_dir).unwrap();
+    std::fs::write(in_dir.join("sample.fa"), ">chr1\nACGT\n").unwrap();
+
+    // A name the regex cannot match used to leave an empty merge behind.
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    let output = cmd.args(["fastixe", "-d", in_dir.to_str().unwrap(), "-m", "-o", out_dir.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("doesn't match regex"));
+    assert!(!out_dir.join("merged.fa").exists());
+
+    // Nor a truncated one, which as BGZF would even end with an EOF block.
+    let bad_dir = out_dir.join("bad");
+    std::fs::create_dir_all(&bad_dir).unwrap();
+    std::fs::write(bad_dir.join("HG002.hap1.fa"), ">chr1\nACGT\n").unwrap();
+    std::fs::write(bad_dir.join("HG003.hap1.fa"), "ACGT\n>chr1\nACGT\n").unwrap();
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args([
+        "fastixe",
+        "-s", bad_dir.join("HG002.hap1.fa").to_str().unwrap(), bad_dir.join("HG003.hap1.fa").to_str().unwrap(),
+        "-r", "^[^.]+",
+        "--delimiter", ".",
+        "-m", "-b", "--faidx",
+        "-o", out_dir.to_str().unwrap(),
+    ])
+    .assert()
+    .code(4);
+    assert!(!out_dir.join("merged.gz").exists());
+    assert!(!out_dir.join("merged.gz.fai").exists());
+
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args(["fastixe", "-d", in_dir.to_str().unwrap(), "-r", "(", "-o", out_dir.to_str().unwrap()])
+        .assert()
+        .code(2);
+
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args(["fastixe", "-i", ".", "-p", "x#0#", "-o", out_dir.to_str().unwrap()])
+        .assert()
+        .code(3);
+
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    let output = cmd.args(["fastixe", "-i", in_dir.join("missing.fa").to_str().unwrap(), "-o", out_dir.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("missing.fa\" does not exist"));
+
+    // Named inputs are never dropped silently, in merge mode either.
+    let merge_dir = out_dir.join("merge");
+    for input in [["-s", "nothere.fa"], ["-l", "nothere.txt"], ["-d", "nothere"]] {
+        let mut cmd = Command::cargo_bin("panutils").unwrap();
+        cmd.arg("fastixe")
+            .args(input)
+            .args(["-m", "-o", merge_dir.to_str().unwrap()])
+            .assert()
+            .code(2);
+    }
+    assert!(!merge_dir.join("merged.fa").exists());
+    std::fs::write(in_dir.join("notes.txt"), "ACGT\n").unwrap();
+    let notes = panutils::fastixe::Fastixe::new().input_file(in_dir.join("notes.txt")).run();
+    assert!(matches!(notes, Err(panutils::Error::Args(_))));
+
+    // The library returns errors instead of exiting.
+    let args = panutils::cmdline::FastixeArgs::default();
+    assert!(matches!(panutils::fastixe::fastixe(args), Err(panutils::Error::Args(_))));
+}
+
+#[test]
+fn test_cli31() {
+    use panutils::fastixe::{Fastixe, Output, PrefixStrategy};
+
+    let out_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_cli31");
+    let in_dir = out_dir.join("inputs");
+    std::fs::create_dir_all(&in_dir).unwrap();
+    std::fs::write(in_dir.join("HG002.hap1.fa"), ">chr1\nACGT\n>chr2\nAC\n").unwrap();
+    std::fs::write(in_dir.join("HG003.hap2.fa"), ">chr1\nTTTT\n").unwrap();
+
+    // No global logger or thread pool, so it can run twice in one process.
+    let merged = out_dir.join("merged.fa");
+    for _ in 0..2 {
+        Fastixe::new()
+            .input_directory(&in_dir)
+            .prefix(PrefixStrategy::Regex(r"^[^.]+".to_string()))
+            .sort(panutils::cmdline::MergeOrder::Name)
+            .min_length(3)
+            .output(Output::Merged { path: merged.clone(), bgzip: false, faidx: false })
+            .threads(2)
+            .run()
+            .unwrap();
+    }
+    assert_eq!(
+        std::fs::read_to_string(&merged).unwrap(),
+        ">HG002#1#chr1\nACGT\n>HG003#2#chr1\nTTTT\n"
+    );
+
+    let separate = out_dir.join("separate");
+    Fastixe::new()
+        .input_file(in_dir.join("HG002.hap1.fa"))
+        .prefix(PrefixStrategy::Fixed("HG002#1#".to_string()))
+        .output(Output::Separate { directory: separate.clone(), gzip: false })
+        .run()
+        .unwrap();
+    assert_eq!(
+        std::fs::read_to_string(separate.join("HG002.hap1.fa")).unwrap(),
+        ">HG002#1#chr1\nACGT\n>HG002#1#chr2\nAC\n"
+    );
+}
+
+#[test]
+fn test_cli32() {
+    let out_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_cli32");
+    let in_dir = out_dir.join("inputs");
+    std::fs::create_dir_all(&in_dir).unwrap();
+    std::fs::write(in_dir.join("HG002.hap1.fa"), ">chr1\nACGT\n>\nAAAA\n>chr2\nGG\n").unwrap();
+    // A truncated download.
+    let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+    for i in 0..50 {
+        writeln!(encoder, ">chr{}\n{}", i, "ACGTTGCA".repeat(500)).unwrap();
+    }
+    let gz = encoder.finish().unwrap();
+    std::fs::write(in_dir.join("HG003.hap1.fa.gz"), &gz[..gz.len() / 2]).unwrap();
+    let regex = r"^[^.]+";
+
+    for merge in [false, true] {
+        let mut cmd = Command::cargo_bin("panutils").unwrap();
+        cmd.args(["fastixe", "-i", in_dir.join("HG002.hap1.fa").to_str().unwrap(), "-r", regex, "-o", out_dir.to_str().unwrap()]);
+        if merge {
+            cmd.arg("-m");
+        }
+        let output = cmd.output().unwrap();
+        assert_eq!(output.status.code(), Some(4));
+        assert!(String::from_utf8_lossy(&output.stderr).contains("HG002.hap1.fa\": record 2 at byte offset 11: header without a record ID"));
+    }
+
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args(["fastixe", "-d", in_dir.to_str().unwrap(), "-r", regex, "-o", out_dir.to_str().unwrap(), "--strict"])
+        .assert()
+        .code(4);
+
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    let output = cmd.args(["fastixe", "-d", in_dir.to_str().unwrap(), "-r", regex, "-o", out_dir.to_str().unwrap(), "--lenient"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert!(log.contains("HG002.hap1.fa\": skipped 1 malformed records"));
+    assert!(log.contains("HG003.hap1.fa.gz\": skipped 1 malformed records"));
+    assert_eq!(
+        std::fs::read_to_string(out_dir.join("HG002.hap1.fa")).unwrap(),
+        ">HG002#1#chr1\nACGT\n>HG002#1#chr2\nGG\n"
+    );
+
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    let output = cmd.args(["fastixe", "-d", in_dir.to_str().unwrap(), "-r", regex, "-o", out_dir.to_str().unwrap(), "-m", "--lenient"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let merged = std::fs::read_to_string(out_dir.join("merged.fa")).unwrap();
+    assert!(merged.starts_with(">HG002#1#chr1\nACGT\n>HG002#1#chr2\nGG\n>HG003#1#chr0\n"));
+
+    // Both outputs keep every complete record and drop the one cut off.
+    let separate = std::fs::read_to_string(out_dir.join("HG003.hap1.fa")).unwrap();
+    let merged = &merged[merged.find(">HG003").unwrap()..];
+    assert_eq!(separate, merged);
+    let records: Vec<_> = separate.split('>').skip(1).collect();
+    assert!(records.len() > 20);
+    for (i, record) in records.iter().enumerate() {
+        assert_eq!(*record, format!("HG003#1#chr{}\n{}\n", i, "ACGTTGCA".repeat(500)));
+    }
+}
+
+#[test]
+fn test_cli33() {
+    let out_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_cli33");
+    let in_dir = out_dir.join("inputs");
+    std::fs::create_dir_all(&in_dir).unwrap();
+    let genome = in_dir.join("HG002.hap1.fa");
+    std::fs::write(&genome, ">chr1\nACGU uRYacgt\n  60 acgtk\n>chr2\nAC*GT\n").unwrap();
+    let regex = r"^[^.]+";
+
+    for merge in [false, true] {
+        let mut cmd = Command::cargo_bin("panutils").unwrap();
+        cmd.args(["fastixe", "-i", genome.to_str().unwrap(), "-r", regex, "-o", out_dir.to_str().unwrap(), "--alphabet", "replace"]);
+        if merge {
+            cmd.arg("-m");
+        }
+        let output = cmd.output().unwrap();
+        assert!(output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains(
+            "converted 2 U to T and 3 ambiguity codes to N, replaced 1 invalid characters with N, stripped 6 spaces and digits"
+        ));
+        let output_file = if merge { "merged.fa" } else { "HG002.hap1.fa" };
+        assert_eq!(
+            std::fs::read_to_string(out_dir.join(output_file)).unwrap(),
+            ">HG002#1#chr1\nACGTtNNacgt\nacgtn\n>HG002#1#chr2\nACNGT\n"
+        );
+    }
+
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    let output = cmd.args(["fastixe", "-i", genome.to_str().unwrap(), "-r", regex, "-o", out_dir.to_str().unwrap(), "--alphabet", "reject"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(4));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("record 2 contains '*'"));
+
+    // Sequences pass through unchanged by default.
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args(["fastixe", "-i", genome.to_str().unwrap(), "-r", regex, "-o", out_dir.to_str().unwrap()])
+        .assert()
+        .success();
+    assert_eq!(
+        std::fs::read_to_string(out_dir.join("HG002.hap1.fa")).unwrap(),
+        ">HG002#1#chr1\nACGU uRYacgt\n  60 acgtk\n>HG002#1#chr2\nAC*GT\n"
+    );
+
+    // Lines of only digits are dropped in both outputs, so the merge can
+    // still be indexed.
+    let numbered = in_dir.join("HG003.hap1.fa");
+    std::fs::write(&numbered, ">chr1\nACGT\n1234\nACGT\nAC\n>chr2\nAC\n99\n").unwrap();
+    let expected = ">HG003#1#chr1\nACGT\nACGT\nAC\n>HG003#1#chr2\nAC\n";
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args(["fastixe", "-i", numbered.to_str().unwrap(), "-r", regex, "-o", out_dir.to_str().unwrap(), "--alphabet", "replace"])
+        .assert()
+        .success();
+    assert_eq!(std::fs::read_to_string(out_dir.join("HG003.hap1.fa")).unwrap(), expected);
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args(["fastixe", "-i", numbered.to_str().unwrap(), "-r", regex, "-o", out_dir.to_str().unwrap(), "--alphabet", "replace", "-m", "-f"])
+        .assert()
+        .success();
+    assert_eq!(std::fs::read_to_string(out_dir.join("merged.fa")).unwrap(), expected);
+    assert_eq!(
+        std::fs::read_to_string(out_dir.join("merged.fa.fai")).unwrap(),
+        "HG003#1#chr1\t10\t14\t4\t5\nHG003#1#chr2\t2\t41\t2\t3\n"
+    );
+}
+
+#[test]
+fn test_cli34() {
+    let out_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_cli34");
+    let in_dir = out_dir.join("inputs");
+    std::fs::create_dir_all(&in_dir).unwrap();
+    let genome = in_dir.join("HG002.hap1.fa");
+    std::fs::write(&genome, ">chr1\nACgt\nnnAC\n>chr2\nacGT\n").unwrap();
+    let regex = r"^[^.]+";
+
+    for merge in [false, true] {
+        let output_file = out_dir.join(if merge { "merged.fa" } else { "HG002.hap1.fa" });
+        for (mode, fasta) in [
+            ("hard", ">HG002#1#chr1\nACNN\nNNAC\n>HG002#1#chr2\nNNGT\n"),
+            ("bed", ">HG002#1#chr1\nACGT\nNNAC\n>HG002#1#chr2\nACGT\n"),
+        ] {
+            let mut cmd = Command::cargo_bin("panutils").unwrap();
+            cmd.args(["fastixe", "-i", genome.to_str().unwrap(), "-r", regex, "-o", out_dir.to_str().unwrap(), "--mask-mode", mode]);
+            if merge {
+                cmd.arg("-m");
+            }
+            cmd.assert().success();
+            assert_eq!(std::fs::read_to_string(&output_file).unwrap(), fasta);
+        }
+        // Intervals are 0-based and half-open, across line breaks.
+        let mut bed = output_file.into_os_string();
+        bed.push(".mask.bed");
+        assert_eq!(std::fs::read_to_string(bed).unwrap(), "HG002#1#chr1\t2\t6\nHG002#1#chr2\t0\t2\n");
+    }
+
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args(["fastixe", "-i", genome.to_str().unwrap(), "-r", regex, "-o", out_dir.to_str().unwrap(), "--up", "--mask-mode", "hard"])
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn test_cli35() {
+    let out_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_cli35");
+    let in_dir = out_dir.join("inputs");
+    std::fs::create_dir_all(&in_dir).unwrap();
+    let fasta = ">chr1\nACGT\n";
+    std::fs::write(in_dir.join("HG002.hap1.fna"), fasta).unwrap();
+    let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+    encoder.write_all(fasta.as_bytes()).unwrap();
+    std::fs::write(in_dir.join("HG002.hap1.fna.gz"), encoder.finish().unwrap()).unwrap();
+
+    // Both inputs would be written to HG002.hap1.fna, which is also one of
+    // the inputs when the output directory is the input directory.
+    for (out, message) in [(&out_dir, "would both be written to"), (&in_dir, "would overwrite the input")] {
+        let mut cmd = Command::cargo_bin("panutils").unwrap();
+        let output = cmd.args(["fastixe", "-d", in_dir.to_str().unwrap(), "-r", "^[^.]+", "-o", out.to_str().unwrap()])
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(2));
+        assert!(String::from_utf8_lossy(&output.stderr).contains(message));
+    }
+    assert_eq!(std::fs::read_to_string(in_dir.join("HG002.hap1.fna")).unwrap(), fasta);
+    assert!(!out_dir.join("HG002.hap1.fna").exists());
+}
+
+#[test]
+fn test_cli36() {
+    let out_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_cli36");
+    std::fs::create_dir_all(&out_dir).unwrap();
+    let merged = out_dir.join("merged.fa");
+    std::fs::write(&merged, ">HG002#1#chr1\nACGT\n>HG002#2#chr1\nGGGG\n").unwrap();
+
+    let split_dir = out_dir.join("sample");
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args(["unfastixe", merged.to_str().unwrap(), "-o", split_dir.to_str().unwrap(), "--by", "sample", "--strip-prefix"])
+        .assert()
+        .code(2);
+    assert!(!split_dir.join("HG002.fa").exists());
+}
+
+#[test]
+fn test_cli37() {
+    let out_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_cli37");
+    let in_dir = out_dir.join("inputs");
+    std::fs::create_dir_all(&in_dir).unwrap();
+    std::fs::write(in_dir.join("HG2.fa"), ">chr1\nACGT\n").unwrap();
+    std::fs::write(in_dir.join("HG3.fa"), ">chr1\nTTTT\n").unwrap();
+
+    // A fixed prefix is used in merge mode too.
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args(["fastixe", "-i", in_dir.join("HG2.fa").to_str().unwrap(), "-p", "X#1#", "-m", "-o", out_dir.to_str().unwrap()])
+        .assert()
+        .success();
+    assert_eq!(std::fs::read_to_string(out_dir.join("merged.fa")).unwrap(), ">X#1#chr1\nACGT\n");
+
+    // And cannot be shared by several genomes.
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args(["fastixe", "-d", in_dir.to_str().unwrap(), "-p", "X#1#", "-o", out_dir.join("separate").to_str().unwrap()])
+        .assert()
+        .code(2);
+    assert!(!out_dir.join("separate").join("HG2.fa").exists());
+}
+
+#[test]
+fn test_cli38() {
+    let out_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_cli38");
+    let in_dir = out_dir.join("inputs");
+    std::fs::create_dir_all(&in_dir).unwrap();
+    std::fs::write(in_dir.join("HG002.hap1.fa"), ">chr1\nACGT\n").unwrap();
+
+    // Levels above 9 used to fall back to the default without a word.
+    let mut cmd = Command::cargo_bin("panutils").unwrap();
+    cmd.args(["fastixe", "-i", in_dir.join("HG002.hap1.fa").to_str().unwrap(), "-m", "-b", "--level", "12", "-o", out_dir.to_str().unwrap()])
+        .assert()
+        .code(2);
+    assert!(!out_dir.join("merged.gz").exists());
+
+    let level = panutils::fastixe::Fastixe::new()
+        .input_file(in_dir.join("HG002.hap1.fa"))
+        .compression_level(12)
+        .run();
+    assert!(matches!(level, Err(panutils::Error::Args(_))));
+}